@@ -0,0 +1,41 @@
+//! Compare decoding the links in `tests/ato_links.txt` repeatedly with and
+//! without a `DecodeCache`. Run it with `cargo bench --features cache`.
+
+use std::{hint::black_box, time::Instant};
+
+use sandbox_links::ato::{DecodeCache, LinkState};
+
+/// The number of times each link is decoded.
+const PASSES: usize = 5;
+
+fn main() {
+    let links = include_str!("../tests/ato_links.txt")
+        .lines()
+        .filter(|link| LinkState::decode(link).is_ok())
+        .collect::<Vec<_>>();
+    println!("{} links, {PASSES} passes", links.len());
+
+    let start = Instant::now();
+    for _ in 0..PASSES {
+        for link in &links {
+            black_box(LinkState::decode(link).unwrap());
+        }
+    }
+    println!("uncached: {:?}", start.elapsed());
+
+    for capacity in [links.len(), links.len() / 2, 64] {
+        let cache = DecodeCache::new(capacity);
+        let start = Instant::now();
+        for _ in 0..PASSES {
+            for link in &links {
+                black_box(cache.get_or_decode(link).unwrap());
+            }
+        }
+        let elapsed = start.elapsed();
+        let stats = cache.stats();
+        println!(
+            "cached, capacity {capacity}: {elapsed:?} ({} hits, {} misses)",
+            stats.hits, stats.misses,
+        );
+    }
+}
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sandbox_links::ato::LinkState;
+
+fuzz_target!(|url: &str| {
+    if let Ok(state) = LinkState::decode(url) {
+        let _ = state.encode();
+    }
+});
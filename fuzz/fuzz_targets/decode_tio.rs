@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sandbox_links::tio::LinkState;
+
+fuzz_target!(|url: &str| {
+    if let Ok(state) = LinkState::decode_v1_bytes(url) {
+        state.encode_v1();
+    }
+});
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+use url::Url;
+
+use crate::{
+    ato,
+    extract::{host_platform, Platform},
+    tio, DecodeOptions,
+};
+
+/// The fields of a share link for either site.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AnyLinkState {
+    Ato(ato::LinkState),
+    Tio(tio::LinkState),
+}
+
+#[derive(Debug, Error)]
+pub enum AnyDecodeError {
+    #[error("URL parse: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("not an ATO or TIO link: {0}")]
+    UnknownPlatform(String),
+    #[error("ATO: {0}")]
+    Ato(#[from] ato::DecodeError),
+    #[error("TIO: {0}")]
+    Tio(#[from] tio::DecodeError),
+}
+
+impl AnyLinkState {
+    /// Decode an ATO or TIO share link, choosing the decoder by its domain.
+    pub fn decode(url: &str) -> Result<Self, AnyDecodeError> {
+        let u = DecodeOptions::default().parse_url(url)?;
+        match url_platform(&u) {
+            Some(Platform::Ato) => Ok(AnyLinkState::Ato(ato::LinkState::decode_url_parsed(&u)?)),
+            Some(Platform::Tio) => Ok(AnyLinkState::Tio(tio::LinkState::decode_url_parsed(&u)?)),
+            None => Err(AnyDecodeError::UnknownPlatform(url.into())),
+        }
+    }
+
+    /// The site that the link is for.
+    pub fn platform(&self) -> Platform {
+        match self {
+            AnyLinkState::Ato(_) => Platform::Ato,
+            AnyLinkState::Tio(_) => Platform::Tio,
+        }
+    }
+}
+
+impl From<ato::LinkState> for AnyLinkState {
+    fn from(state: ato::LinkState) -> Self {
+        AnyLinkState::Ato(state)
+    }
+}
+
+impl From<tio::LinkState> for AnyLinkState {
+    fn from(state: tio::LinkState) -> Self {
+        AnyLinkState::Tio(state)
+    }
+}
+
+/// Get the language of an ATO or TIO share link, without decoding the rest of
+/// it when possible, for filtering many links quickly. Returns `None` when the
+/// link has no language or cannot be decoded.
+///
+/// An ATO link with only the `L` key has the language in the query string, but
+/// when it has data, the language in the data takes precedence, so it is fully
+/// decoded. A TIO link has the language in its domain, path, `lang` query key,
+/// or the start of its fragment.
+pub fn quick_language(url: &str) -> Option<String> {
+    let u = DecodeOptions::default().parse_url(url).ok()?;
+    let language = match url_platform(&u)? {
+        Platform::Ato => {
+            if u.query_pairs().any(|(key, _)| key == "0" || key == "1") {
+                ato::LinkState::decode_url_parsed(&u).ok()?.language
+            } else {
+                let (_, l) = u.query_pairs().find(|(key, _)| key == "L" || key == "l")?;
+                l.into_owned()
+            }
+        }
+        Platform::Tio => {
+            let domain = u.domain()?;
+            if let Some(l) = domain.strip_suffix(".tryitonline.net") {
+                l.to_owned()
+            } else if let Some(l) = u.path().strip_prefix("/nexus/") {
+                l.to_owned()
+            } else if let Some((_, l)) = u.query_pairs().find(|(key, _)| key == "lang") {
+                l.into_owned()
+            } else {
+                let (l, _) = u.fragment()?.split_once('#')?;
+                l.to_owned()
+            }
+        }
+    };
+    Some(language).filter(|l| !l.is_empty())
+}
+
+/// Count the links for each language in a corpus of ATO and TIO share links,
+/// for reports of which languages are shared most. Languages are found with
+/// [`quick_language`], and links without one, including those which cannot be
+/// decoded, are skipped.
+pub fn language_histogram<'a>(links: impl Iterator<Item = &'a str>) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for language in links.filter_map(quick_language) {
+        *counts.entry(language).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Get the site of a URL by its domain.
+fn url_platform(u: &Url) -> Option<Platform> {
+    host_platform(u.domain()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        let state = AnyLinkState::decode("https://ato.pxeger.com/run?L=python").unwrap();
+        assert_eq!(state.platform(), Platform::Ato);
+        let state = AnyLinkState::decode("https://tio.run/#sed#code=cC8&input=").unwrap();
+        assert_eq!(state.platform(), Platform::Tio);
+        assert!(matches!(
+            AnyLinkState::decode("https://example.com/run?L=python"),
+            Err(AnyDecodeError::UnknownPlatform(_)),
+        ));
+    }
+
+    #[test]
+    fn quick_language() {
+        for (url, language) in [
+            ("https://ato.pxeger.com/run?L=python", Some("python")),
+            (
+                "https://ato.pxeger.com/run?1=m72soLIkIz9vwYIFKwqKMvNKNAw1F4ABAA",
+                Some("python"),
+            ),
+            ("https://ato.pxeger.com/run?1=", None),
+            ("https://tio.run/#sed#code=cC8&input=", Some("sed")),
+            ("https://tio.run/nexus/retina#code=&input=", Some("retina")),
+            ("https://tio.run/?lang=sed#code=cA&input=", Some("sed")),
+            ("http://cubically.tryitonline.net/", Some("cubically")),
+            ("https://tio.run/##K8jILM5ITVGwUtBUSVPIKM3NBQA", None),
+            ("https://example.com/run?L=python", None),
+            ("ato.pxeger.com/run?L=python", Some("python")),
+        ] {
+            assert_eq!(super::quick_language(url).as_deref(), language, "{url}");
+        }
+    }
+
+    #[test]
+    fn language_histogram() {
+        let links = [
+            "https://ato.pxeger.com/run?L=python",
+            "https://ato.pxeger.com/run?1=m72soLIkIz9vwYIFKwqKMvNKNAw1F4ABAA",
+            "https://tio.run/#sed#code=cC8&input=",
+            "https://tio.run/##K05N0U3PK81JLSrWBQA",
+            "https://ato.pxeger.com/run?1=",
+            "https://ato.pxeger.com/run?1=!!!",
+            "not a link",
+        ];
+        let histogram = super::language_histogram(links.into_iter());
+        assert_eq!(
+            histogram,
+            HashMap::from([("python".into(), 2), ("sed".into(), 1)]),
+        );
+    }
+}
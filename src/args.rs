@@ -0,0 +1,229 @@
+use std::{
+    ops::{Deref, DerefMut},
+    slice, vec,
+};
+
+use thiserror::Error;
+
+/// The arguments for a program, as ATO `program_arguments` or TIO `args`,
+/// which can be written as a shell command line.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Args(pub Vec<String>);
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ShellParseError {
+    #[error("unterminated {0} quote")]
+    UnterminatedQuote(char),
+    #[error("trailing backslash")]
+    TrailingBackslash,
+}
+
+impl Args {
+    /// Write the arguments as a POSIX shell command line, quoting each one
+    /// that is empty or has characters special to the shell.
+    pub fn to_shell(&self) -> String {
+        let mut line = String::new();
+        for (i, arg) in self.0.iter().enumerate() {
+            if i != 0 {
+                line.push(' ');
+            }
+            let is_plain = |c: char| c.is_ascii_alphanumeric() || "%+,-./:=@_".contains(c);
+            if !arg.is_empty() && arg.chars().all(is_plain) {
+                line.push_str(arg);
+            } else {
+                // Nothing is special in single quotes, so a single quote is
+                // written by closing the quotes and escaping it.
+                line.push('\'');
+                line.push_str(&arg.replace('\'', r"'\''"));
+                line.push('\'');
+            }
+        }
+        line
+    }
+
+    /// Split a POSIX shell command line into arguments, handling single
+    /// quotes, double quotes, and backslashes, like the shell does. Nothing
+    /// is expanded, so `$x` or `*` are kept literally.
+    pub fn from_shell(line: &str) -> Result<Self, ShellParseError> {
+        let mut args = Vec::new();
+        let mut arg = None::<String>;
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' | '\t' | '\n' => {
+                    if let Some(arg) = arg.take() {
+                        args.push(arg);
+                    }
+                }
+                '\'' => {
+                    let arg = arg.get_or_insert_with(String::new);
+                    loop {
+                        match chars.next() {
+                            Some('\'') => break,
+                            Some(c) => arg.push(c),
+                            None => return Err(ShellParseError::UnterminatedQuote('\'')),
+                        }
+                    }
+                }
+                '"' => {
+                    let arg = arg.get_or_insert_with(String::new);
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            // In double quotes, a backslash only escapes
+                            // characters which are otherwise special.
+                            Some('\\') => match chars.next() {
+                                Some('\n') => {}
+                                Some(c @ ('$' | '`' | '"' | '\\')) => arg.push(c),
+                                Some(c) => {
+                                    arg.push('\\');
+                                    arg.push(c);
+                                }
+                                None => return Err(ShellParseError::UnterminatedQuote('"')),
+                            },
+                            Some(c) => arg.push(c),
+                            None => return Err(ShellParseError::UnterminatedQuote('"')),
+                        }
+                    }
+                }
+                '\\' => match chars.next() {
+                    // A line continuation
+                    Some('\n') => {}
+                    Some(c) => arg.get_or_insert_with(String::new).push(c),
+                    None => return Err(ShellParseError::TrailingBackslash),
+                },
+                c => arg.get_or_insert_with(String::new).push(c),
+            }
+        }
+        args.extend(arg);
+        Ok(Args(args))
+    }
+}
+
+impl From<Vec<String>> for Args {
+    fn from(args: Vec<String>) -> Self {
+        Args(args)
+    }
+}
+
+impl From<Args> for Vec<String> {
+    fn from(args: Args) -> Self {
+        args.0
+    }
+}
+
+impl FromIterator<String> for Args {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+        Args(iter.into_iter().collect())
+    }
+}
+
+impl Deref for Args {
+    type Target = Vec<String>;
+
+    fn deref(&self) -> &Vec<String> {
+        &self.0
+    }
+}
+
+impl DerefMut for Args {
+    fn deref_mut(&mut self) -> &mut Vec<String> {
+        &mut self.0
+    }
+}
+
+impl IntoIterator for Args {
+    type Item = String;
+    type IntoIter = vec::IntoIter<String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Args {
+    type Item = &'a String;
+    type IntoIter = slice::Iter<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_roundtrip() {
+        let args = Args(
+            [
+                "-n",
+                "",
+                "a b",
+                "it's",
+                "\"q\"",
+                "$HOME",
+                "*",
+                "back\\slash",
+                "tab\tnew\nline",
+                "ünïcode",
+            ]
+            .map(String::from)
+            .to_vec(),
+        );
+        let line = args.to_shell();
+        assert_eq!(
+            line,
+            "-n '' 'a b' 'it'\\''s' '\"q\"' '$HOME' '*' 'back\\slash' 'tab\tnew\nline' 'ünïcode'",
+        );
+        assert_eq!(Args::from_shell(&line).unwrap(), args);
+    }
+
+    #[test]
+    fn iterate() {
+        let mut state = crate::tio::LinkState {
+            args: Args(vec!["a".into(), "b".into()]),
+            ..Default::default()
+        };
+        assert_eq!(state.args.len(), 2);
+        assert_eq!(state.args[0], "a");
+        let mut seen = Vec::new();
+        for arg in &state.args {
+            seen.push(arg.as_str());
+        }
+        assert_eq!(seen, ["a", "b"]);
+        state.args.push("c".into());
+        state.args.extend(["d".into()]);
+        assert_eq!(*state.args, ["a", "b", "c", "d"]);
+        state.args.truncate(2);
+        let mut owned = Vec::new();
+        for arg in state.args {
+            owned.push(arg);
+        }
+        assert_eq!(owned, ["a", "b"]);
+    }
+
+    #[test]
+    fn from_shell() {
+        let args = |args: &[&str]| Args(args.iter().map(|&a| a.into()).collect());
+        assert_eq!(Args::from_shell("").unwrap(), args(&[]));
+        assert_eq!(Args::from_shell("  a\t b  ").unwrap(), args(&["a", "b"]));
+        assert_eq!(
+            Args::from_shell("a\"b c\"'d e' \"\" x\\ y \"\\$\\a\" a\\\nb").unwrap(),
+            args(&["ab cd e", "", "x y", "$\\a", "ab"]),
+        );
+        assert_eq!(
+            Args::from_shell("'a"),
+            Err(ShellParseError::UnterminatedQuote('\'')),
+        );
+        assert_eq!(
+            Args::from_shell("\"a\\\""),
+            Err(ShellParseError::UnterminatedQuote('"')),
+        );
+        assert_eq!(
+            Args::from_shell("a\\"),
+            Err(ShellParseError::TrailingBackslash),
+        );
+    }
+}
@@ -1,31 +1,381 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+#[cfg(feature = "net")]
+use std::collections::{BTreeMap, HashMap};
 
+#[cfg(feature = "net")]
 use lazy_static::lazy_static;
 use serde::Deserialize;
 
-pub(crate) const RUN_URL: &'static str = "https://ato.pxeger.com/run";
-pub(crate) const LANGUAGES_URL: &'static str = "https://ato.pxeger.com/languages.json";
+pub(crate) const RUN_URL: &str = "https://ato.pxeger.com/run";
+#[cfg(feature = "net")]
+pub(crate) const LANGUAGES_URL: &str = "https://ato.pxeger.com/languages.json";
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
 pub struct Language {
+    /// The key of the language in `languages.json`, which links use.
+    #[serde(skip)]
+    id: String,
     name: String,
     image: String,
+    // Newly-added languages may lack the other fields until their container
+    // is built, so they are optional. Unknown fields are ignored.
+    #[serde(default)]
     version: String,
+    #[serde(default)]
     url: String,
+    #[serde(default)]
     sbcs: bool,
+    #[serde(default)]
     se_class: Option<String>,
 }
 
+#[cfg(feature = "net")]
 pub fn get_languages() -> &'static HashMap<String, Language> {
     lazy_static! {
         static ref LANGUAGES: HashMap<String, Language> = {
             let resp = reqwest::blocking::get(LANGUAGES_URL).unwrap();
-            resp.json().unwrap()
+            let mut languages: HashMap<String, Language> = resp.json().unwrap();
+            for (id, language) in &mut languages {
+                language.id = id.clone();
+            }
+            languages
         };
     }
     &LANGUAGES
 }
 
+impl Language {
+    /// The id of the language, as used in links.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The display name of the language.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The version of the language implementation, e.g., `3.11.4`.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The homepage of the language.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The class for highlighting the language on Stack Exchange, e.g.,
+    /// `lang-py`. Many languages have none.
+    pub fn se_class(&self) -> Option<&str> {
+        self.se_class.as_deref()
+    }
+
+    /// Whether the language uses a single-byte character set, so its code
+    /// is written in a code page.
+    pub fn is_sbcs(&self) -> bool {
+        self.sbcs
+    }
+
+    /// Compare languages by name, ignoring case, like a language picker lists
+    /// them. Ties are broken by the exact name and then by id, so the order
+    /// is total.
+    pub fn cmp_by_name(&self, other: &Language) -> Ordering {
+        let lower = |name: &str| {
+            name.chars()
+                .flat_map(char::to_lowercase)
+                .collect::<Vec<_>>()
+        };
+        lower(&self.name)
+            .cmp(&lower(&other.name))
+            .then_with(|| self.name.cmp(&other.name))
+            .then_with(|| self.id.cmp(&other.id))
+    }
+
+    /// Guess the kind of the language for grouping in a language picker.
+    /// `languages.json` has no categories, so languages in
+    /// `CATEGORY_OVERRIDES` use that and others are golfing languages if they
+    /// use a single-byte character set, practical languages if Stack Exchange
+    /// highlights them, and otherwise esoteric.
+    pub fn category(&self) -> Category {
+        if let Ok(i) = CATEGORY_OVERRIDES.binary_search_by_key(&&*self.id, |&(id, _)| id) {
+            CATEGORY_OVERRIDES[i].1
+        } else if self.sbcs {
+            Category::Golfing
+        } else if self.se_class.is_some() {
+            Category::Practical
+        } else {
+            Category::Esoteric
+        }
+    }
+}
+
+/// A few languages in the format of `languages.json`, for tests.
+#[cfg(test)]
+const TEST_LANGUAGES: &str = r#"{
+    "apl_dyalog": {"name": "APL (Dyalog APL)", "image": "attemptthisonline/dyalog_apl", "version": "18.2", "url": "https://www.dyalog.com", "sbcs": true, "se_class": null},
+    "awk": {"name": "awk", "image": "attemptthisonline/awk", "version": "5.2", "url": "https://www.gnu.org/software/gawk", "sbcs": false, "se_class": null},
+    "bash": {"name": "Bash", "image": "attemptthisonline/bash", "version": "5.2", "url": "https://www.gnu.org/software/bash", "sbcs": false, "se_class": "lang-sh"},
+    "cython": {"name": "Cython", "image": "attemptthisonline/python", "version": "0.29", "url": "https://cython.org", "sbcs": false, "se_class": null},
+    "jelly": {"name": "Jelly", "image": "attemptthisonline/jelly", "version": "70c9fd93", "url": "https://github.com/DennisMitchell/jellylanguage", "sbcs": true, "se_class": null},
+    "knight": {"name": "Knight", "image": "attemptthisonline/knight", "version": "2", "url": "https://github.com/knight-lang/knight-lang", "sbcs": false, "se_class": null},
+    "pyth": {"name": "Pyth", "image": "attemptthisonline/pyth", "version": "1", "url": "https://github.com/isaacg1/pyth", "sbcs": false, "se_class": null},
+    "python": {"name": "Python", "image": "attemptthisonline/python", "version": "3.11.4", "url": "https://www.python.org", "sbcs": false, "se_class": "lang-py"},
+    "python2": {"name": "Python 2", "image": "attemptthisonline/python2", "version": "2.7.18", "url": "https://www.python.org", "sbcs": false, "se_class": "lang-py"},
+    "pythonx": {"name": "Python X", "image": "attemptthisonline/pythonx", "version": "3.110", "url": "https://www.python.org", "sbcs": false, "se_class": null},
+    "zsh": {"name": "Zsh", "image": "attemptthisonline/zsh", "version": "5.9", "url": "https://www.zsh.org", "sbcs": false, "se_class": "lang-sh"}
+}"#;
+
+#[cfg(test)]
+impl Language {
+    /// Get the languages with the given ids from the test fixture, like
+    /// [`get_languages`] returns them.
+    pub(crate) fn test_languages(ids: &[&str]) -> std::collections::HashMap<String, Language> {
+        let mut languages: std::collections::HashMap<String, Language> =
+            serde_json::from_str(TEST_LANGUAGES).unwrap();
+        languages.retain(|id, _| ids.contains(&&**id));
+        assert_eq!(languages.len(), ids.len(), "unknown test language");
+        for (id, language) in &mut languages {
+            language.id = id.clone();
+        }
+        languages
+    }
+
+    /// Get a language from the test fixture, leaked so it can be used in a
+    /// [`State`](crate::ato::State), like [`get_language`] returns it.
+    pub(crate) fn test_language(id: &str) -> &'static Language {
+        let language = Language::test_languages(&[id]).remove(id).unwrap();
+        Box::leak(Box::new(language))
+    }
+}
+
+/// A kind of language, for grouping languages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Category {
+    /// General-purpose languages.
+    Practical,
+    /// Languages designed for code golf.
+    Golfing,
+    /// Other languages, such as esoteric or experimental ones.
+    Esoteric,
+}
+
+/// Categories for languages that the heuristic in [`Language::category`] gets
+/// wrong, sorted by id. Most are golfing languages that use ASCII.
+const CATEGORY_OVERRIDES: &[(&str, Category)] = &[
+    ("brainfuck", Category::Esoteric),
+    ("golfscript", Category::Golfing),
+    ("j_uby", Category::Golfing),
+    ("nibbles", Category::Golfing),
+    ("pip", Category::Golfing),
+    ("pyth", Category::Golfing),
+    ("slashes", Category::Esoteric),
+];
+
+#[cfg(feature = "net")]
 pub fn get_language(name: &str) -> Option<&'static Language> {
     get_languages().get(name)
 }
+
+/// Get the languages that run in the given container image, sorted by name.
+#[cfg(feature = "net")]
+pub fn get_languages_by_image(image: &str) -> Vec<&'static Language> {
+    languages_by_image(get_languages(), image)
+}
+
+/// Get the languages that Stack Exchange highlights with the given class,
+/// e.g., `lang-py`, sorted by name. The `lang-` prefix may be omitted.
+#[cfg(feature = "net")]
+pub fn get_languages_by_se_class(class: &str) -> Vec<&'static Language> {
+    languages_by_se_class(get_languages(), class)
+}
+
+/// Get the languages with an id starting with `prefix` and the given version,
+/// sorted by id. A version matches when it is equal or `version` is a prefix
+/// of it that ends at a component, so `3.11` matches `3.11.4`, but not
+/// `3.110`.
+///
+/// Links only have the language id and cannot select a version, so pinning a
+/// version means choosing the id of a language with that version. ATO has
+/// separate ids for some versions of a language, but most have only one.
+#[cfg(feature = "net")]
+pub fn get_languages_matching_version(prefix: &str, version: &str) -> Vec<&'static Language> {
+    languages_matching_version(get_languages(), prefix, version)
+}
+
+#[cfg(feature = "net")]
+fn languages_matching_version<'a>(
+    languages: &'a HashMap<String, Language>,
+    prefix: &str,
+    version: &str,
+) -> Vec<&'a Language> {
+    let mut matches: Vec<&Language> = languages
+        .values()
+        .filter(|l| {
+            l.id.starts_with(prefix)
+                && l.version
+                    .strip_prefix(version)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+        .collect();
+    matches.sort_by(|a, b| a.id.cmp(&b.id));
+    matches
+}
+
+/// Get all languages, sorted by [`Language::cmp_by_name`], for listing them in
+/// a stable order.
+#[cfg(feature = "net")]
+pub fn sorted_languages() -> Vec<&'static Language> {
+    sorted_by_name(get_languages())
+}
+
+#[cfg(feature = "net")]
+fn sorted_by_name(languages: &HashMap<String, Language>) -> Vec<&Language> {
+    let mut sorted: Vec<&Language> = languages.values().collect();
+    sorted.sort_by(|a, b| a.cmp_by_name(b));
+    sorted
+}
+
+/// Get the languages grouped by [`Language::category`], each sorted by name.
+#[cfg(feature = "net")]
+pub fn language_categories() -> BTreeMap<Category, Vec<&'static Language>> {
+    group_by_category(get_languages())
+}
+
+#[cfg(feature = "net")]
+fn group_by_category(languages: &HashMap<String, Language>) -> BTreeMap<Category, Vec<&Language>> {
+    let mut categories: BTreeMap<Category, Vec<&Language>> = BTreeMap::new();
+    for language in languages.values() {
+        categories
+            .entry(language.category())
+            .or_default()
+            .push(language);
+    }
+    for languages in categories.values_mut() {
+        languages.sort_by(|a, b| a.cmp_by_name(b));
+    }
+    categories
+}
+
+#[cfg(feature = "net")]
+fn languages_by_image<'a>(
+    languages: &'a HashMap<String, Language>,
+    image: &str,
+) -> Vec<&'a Language> {
+    let mut matches: Vec<&Language> = languages.values().filter(|l| l.image == image).collect();
+    matches.sort_by(|a, b| a.cmp_by_name(b));
+    matches
+}
+
+#[cfg(feature = "net")]
+fn languages_by_se_class<'a>(
+    languages: &'a HashMap<String, Language>,
+    class: &str,
+) -> Vec<&'a Language> {
+    let class = class.strip_prefix("lang-").unwrap_or(class);
+    let mut matches: Vec<&Language> = languages
+        .values()
+        .filter(|l| l.se_class().and_then(|c| c.strip_prefix("lang-")) == Some(class))
+        .collect();
+    matches.sort_by(|a, b| a.cmp_by_name(b));
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn by_image() {
+        let languages = Language::test_languages(&["python", "cython", "zsh"]);
+        let python = languages_by_image(&languages, "attemptthisonline/python");
+        assert_eq!(python, [&languages["cython"], &languages["python"]]);
+        assert_ne!(python[0], python[1]);
+        assert!(languages_by_image(&languages, "attemptthisonline/jelly").is_empty());
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn by_se_class() {
+        let languages = Language::test_languages(&["python", "cython", "bash", "zsh"]);
+        let sh = languages_by_se_class(&languages, "lang-sh");
+        assert_eq!(sh, [&languages["bash"], &languages["zsh"]]);
+        assert_eq!(languages_by_se_class(&languages, "sh"), sh);
+        assert_eq!(
+            languages_by_se_class(&languages, "lang-py"),
+            [&languages["python"]],
+        );
+        assert!(languages_by_se_class(&languages, "lang-c").is_empty());
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn matching_version() {
+        let languages = Language::test_languages(&["python", "python2", "pythonx"]);
+        let ids = |matches: Vec<&Language>| -> Vec<String> {
+            matches.iter().map(|l| l.id().to_owned()).collect()
+        };
+        assert_eq!(
+            ids(languages_matching_version(&languages, "python", "3.11")),
+            ["python"]
+        );
+        assert_eq!(
+            ids(languages_matching_version(&languages, "python", "2")),
+            ["python2"]
+        );
+        assert_eq!(
+            ids(languages_matching_version(&languages, "py", "3.11.4")),
+            ["python"],
+        );
+        assert!(languages_matching_version(&languages, "python", "3.1").is_empty());
+        assert_eq!(languages["python"].name(), "Python");
+        assert_eq!(languages["python"].version(), "3.11.4");
+    }
+
+    #[test]
+    fn sorted() {
+        assert!(CATEGORY_OVERRIDES.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn sorted_by_name() {
+        let languages = Language::test_languages(&["zsh", "apl_dyalog", "awk", "bash"]);
+        let ids = |sorted: Vec<&Language>| -> Vec<String> {
+            sorted.iter().map(|l| l.id().to_owned()).collect()
+        };
+        let sorted = ids(super::sorted_by_name(&languages));
+        assert_eq!(sorted, ["apl_dyalog", "awk", "bash", "zsh"]);
+        assert_eq!(sorted, ids(super::sorted_by_name(&languages.clone())));
+    }
+
+    #[test]
+    fn minimal() {
+        let language: Language = serde_json::from_str(
+            r#"{"name": "Foo", "image": "attemptthisonline/foo", "logo": "foo.svg"}"#,
+        )
+        .unwrap();
+        assert_eq!(language.name(), "Foo");
+        assert_eq!(language.version(), "");
+        assert!(!language.is_sbcs());
+        assert_eq!(language.se_class, None);
+    }
+
+    #[test]
+    fn category() {
+        let languages = Language::test_languages(&["python", "jelly", "pyth", "knight"]);
+        assert_eq!(languages["python"].category(), Category::Practical);
+        assert_eq!(languages["jelly"].category(), Category::Golfing);
+        assert_eq!(languages["pyth"].category(), Category::Golfing);
+        assert_eq!(languages["knight"].category(), Category::Esoteric);
+        #[cfg(feature = "net")]
+        {
+            let categories = group_by_category(&languages);
+            assert_eq!(
+                categories[&Category::Golfing],
+                [&languages["jelly"], &languages["pyth"]],
+            );
+        }
+    }
+}
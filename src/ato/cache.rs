@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::ato::{DecodeError, LinkState};
+
+/// A cache of decoded links by URL, for services that decode the same links
+/// repeatedly. When full, the least recently used link is evicted. It can be
+/// shared between threads. `benches/decode_cache.rs` compares decoding the
+/// corpus with and without it.
+#[derive(Debug)]
+pub struct DecodeCache {
+    capacity: usize,
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Debug, Default)]
+struct CacheInner {
+    entries: HashMap<String, CacheEntry>,
+    /// Incremented on every access, to order entries by recency.
+    clock: u64,
+    stats: CacheStats,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    state: Arc<LinkState>,
+    last_used: u64,
+}
+
+/// The number of lookups which were or were not in the cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl DecodeCache {
+    /// Construct a cache which holds at most `capacity` links.
+    pub fn new(capacity: usize) -> Self {
+        DecodeCache {
+            capacity,
+            inner: Mutex::new(CacheInner::default()),
+        }
+    }
+
+    /// Get a link from the cache or decode it with [`LinkState::decode`].
+    /// Links which fail to decode are not cached. The lock is not held while
+    /// decoding, so a link decoded concurrently by several threads may be
+    /// decoded more than once.
+    pub fn get_or_decode(&self, url: &str) -> Result<Arc<LinkState>, DecodeError> {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.clock += 1;
+            let clock = inner.clock;
+            if let Some(entry) = inner.entries.get_mut(url) {
+                entry.last_used = clock;
+                let state = entry.state.clone();
+                inner.stats.hits += 1;
+                return Ok(state);
+            }
+            inner.stats.misses += 1;
+        }
+        let state = Arc::new(LinkState::decode(url)?);
+        if self.capacity != 0 {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.entries.len() >= self.capacity && !inner.entries.contains_key(url) {
+                // Finding the least recently used entry is linear. In
+                // `benches/decode_cache.rs`, that is cheap next to decoding
+                // with 64 links, but costs more than decoding with thousands.
+                let oldest = inner
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(url, _)| url.clone());
+                if let Some(oldest) = oldest {
+                    inner.entries.remove(&oldest);
+                }
+            }
+            let last_used = inner.clock;
+            inner.entries.insert(
+                url.to_owned(),
+                CacheEntry {
+                    state: state.clone(),
+                    last_used,
+                },
+            );
+        }
+        Ok(state)
+    }
+
+    /// The number of links in the cache.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Reports whether the cache has no links.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The hits and misses of lookups so far.
+    pub fn stats(&self) -> CacheStats {
+        self.inner.lock().unwrap().stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru() {
+        let url = |l: &str| format!("https://ato.pxeger.com/run?L={l}");
+        let cache = DecodeCache::new(2);
+        let a = cache.get_or_decode(&url("a")).unwrap();
+        assert_eq!(a.language, "a");
+        cache.get_or_decode(&url("b")).unwrap();
+        assert!(Arc::ptr_eq(&a, &cache.get_or_decode(&url("a")).unwrap()));
+        // `b` is least recently used, so it is evicted
+        cache.get_or_decode(&url("c")).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&a, &cache.get_or_decode(&url("a")).unwrap()));
+        cache.get_or_decode(&url("b")).unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 4 });
+
+        assert!(cache
+            .get_or_decode("https://ato.pxeger.com/run?x=1")
+            .is_err());
+        assert_eq!(cache.len(), 2);
+    }
+}
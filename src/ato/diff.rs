@@ -0,0 +1,118 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::ato::State;
+
+/// The fields that differ between two states.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub language: bool,
+    pub options: bool,
+    pub header: bool,
+    pub header_encoding: bool,
+    pub code: bool,
+    pub code_encoding: bool,
+    pub footer: bool,
+    pub footer_encoding: bool,
+    pub program_arguments: bool,
+    pub input: bool,
+    pub input_encoding: bool,
+}
+
+impl State {
+    /// Report which fields differ between this state and `other`.
+    pub fn diff(&self, other: &State) -> StateDiff {
+        StateDiff {
+            language: self.language != other.language,
+            options: self.options != other.options || self.scalar_options != other.scalar_options,
+            header: self.header != other.header,
+            header_encoding: self.header_encoding != other.header_encoding,
+            code: self.code != other.code,
+            code_encoding: self.code_encoding != other.code_encoding,
+            footer: self.footer != other.footer,
+            footer_encoding: self.footer_encoding != other.footer_encoding,
+            program_arguments: self.program_arguments != other.program_arguments
+                || self.scalar_program_arguments != other.scalar_program_arguments,
+            input: self.input != other.input,
+            input_encoding: self.input_encoding != other.input_encoding,
+        }
+    }
+}
+
+impl StateDiff {
+    /// Reports whether no fields differ.
+    pub fn is_empty(&self) -> bool {
+        self.changed().next().is_none()
+    }
+
+    /// The names of the fields that differ, in field order.
+    pub fn changed(&self) -> impl Iterator<Item = &'static str> {
+        [
+            (self.language, "language"),
+            (self.options, "options"),
+            (self.header, "header"),
+            (self.header_encoding, "header encoding"),
+            (self.code, "code"),
+            (self.code_encoding, "code encoding"),
+            (self.footer, "footer"),
+            (self.footer_encoding, "footer encoding"),
+            (self.program_arguments, "program arguments"),
+            (self.input, "input"),
+            (self.input_encoding, "input encoding"),
+        ]
+        .into_iter()
+        .filter_map(|(changed, name)| changed.then_some(name))
+    }
+}
+
+impl Display for StateDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no changes");
+        }
+        write!(f, "changed ")?;
+        for (i, name) in self.changed().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{name}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ato::Encoding, Args};
+
+    #[test]
+    fn diff() {
+        let old = State {
+            code: "print(1)".into(),
+            ..State::default()
+        };
+        assert!(old.diff(&old).is_empty());
+        assert_eq!(old.diff(&old).to_string(), "no changes");
+
+        let new = State {
+            code: "cHJpbnQoMik=".into(),
+            code_encoding: Encoding::Base64,
+            program_arguments: Args(vec!["1".into()]),
+            ..old.clone()
+        };
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff,
+            StateDiff {
+                code: true,
+                code_encoding: true,
+                program_arguments: true,
+                ..StateDiff::default()
+            },
+        );
+        assert_eq!(
+            diff.to_string(),
+            "changed code, code encoding, program arguments",
+        );
+    }
+}
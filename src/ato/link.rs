@@ -1,17 +1,36 @@
-use std::io::{self, BufRead, Read};
+use std::{
+    borrow::Cow,
+    fmt::{self, Display, Formatter},
+    io::{self, Read},
+};
 
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use flate2::{
-    bufread::{DeflateDecoder, DeflateEncoder},
-    Compression,
+    bufread::{DeflateDecoder, GzDecoder},
+    Compress, Compression, FlushCompress, Status,
 };
 use lazy_static::lazy_static;
+use percent_encoding::percent_decode_str;
 use regex::bytes::Regex;
+use serde::{
+    de::{DeserializeSeed, IgnoredAny, SeqAccess, Visitor},
+    Deserializer, Serialize,
+};
 use thiserror::Error;
-use url::Url;
+use url::{form_urlencoded, Url};
 
-use crate::ato::RUN_URL;
+use crate::{
+    ato::RUN_URL,
+    corpus::{CorpusFailure, CorpusReport},
+    options::{Base64Mode, DecodeOptions, DecodeReport, EncodeOptions, ProgressCallback},
+};
 
+/// The fields of an Attempt This Online link, as they are written in it.
+///
+/// Encodings are kept verbatim and are not minified: ATO writes `utf-8`
+/// explicitly, rather than an empty string, for the default encoding. In the
+/// links in `tests/ato_links.txt`, 19244 encodings are `utf-8` and only 12 are
+/// empty, so writing empty encodings would not match the reference.
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct LinkState {
     pub schema: LinkSchema,
@@ -26,31 +45,59 @@ pub struct LinkState {
     pub program_arguments: String,
     pub input: String,
     pub input_encoding: String,
+    /// Query keys other than the data and language, such as UI flags, in
+    /// order. These are only collected by [`LinkState::decode_permissive`]
+    /// and are written after the data when encoding.
+    pub extra_query: Vec<(String, String)>,
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub enum LinkSchema {
     V0,
     #[default]
     V1,
 }
 
+/// The keys accepted in the query string of an ATO link: the data for schema
+/// version 0 or 1 and the language. Other keys are unknown.
+pub const ATO_QUERY_KEYS: &[&str] = &["0", "1", "L", "l"];
+
+/// The decompressed data with its schema, the language, and the unknown keys
+/// from the query string.
+type DecodedUrl = (
+    Option<(LinkSchema, Vec<u8>)>,
+    Option<String>,
+    Vec<(String, String)>,
+);
+
 #[derive(Debug, Error)]
 pub enum DecodeError {
     #[error("URL parse: {0}")]
     Url(#[from] url::ParseError),
     #[error("unknown key `{0}` in query string")]
     UnknownKey(String),
-    #[error("multiple schema versions")]
-    MultipleVersions,
+    #[error("multiple schema versions: {0:?} and {1:?} (ATO would use the maximum)")]
+    MultipleVersions(LinkSchema, LinkSchema),
     #[error("multiple languages")]
     MultipleLanguages,
     #[error("base64 decode: {0}")]
     Base64(#[from] base64::DecodeError),
     #[error("DEFLATE decompress: {0}")]
     Deflate(#[from] io::Error),
+    #[error("unexpected zlib header: ATO uses raw DEFLATE, so strip the 2-byte header and 4-byte Adler-32 trailer")]
+    UnexpectedZlibHeader,
+    #[error("decompressed data is larger than {0} bytes")]
+    TooLarge(u64),
+    #[error("decompression cancelled after {0} bytes")]
+    Cancelled(u64),
     #[error("MessagePack deserialize: {0}")]
     MessagePack(#[from] rmp_serde::decode::Error),
+    #[error("schema version {schema:?} has {expected} fields, but the data has {found}")]
+    SchemaMismatch {
+        schema: LinkSchema,
+        expected: usize,
+        found: usize,
+    },
 }
 
 #[derive(Debug, Error)]
@@ -59,6 +106,72 @@ pub enum EncodeError {
     MessagePack(#[from] rmp_serde::encode::Error),
     #[error("DEFLATE compress: {0}")]
     Deflate(#[from] io::Error),
+    #[error("write: {0}")]
+    Write(#[from] fmt::Error),
+    #[error("URL parse: {0}")]
+    Url(#[from] url::ParseError),
+}
+
+/// The size in bytes of a link at each stage of encoding. For a link with only
+/// a language, only the URL is nonzero, since it has no data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    /// The MessagePack-serialized fields.
+    pub raw_mp: usize,
+    /// The DEFLATE-compressed MessagePack.
+    pub compressed: usize,
+    /// The base64-encoded compressed data.
+    pub base64: usize,
+    /// The full URL.
+    pub url: usize,
+}
+
+/// Settings for DEFLATE-compressing the data of a link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompressionSettings {
+    /// The compression level, from 0 to 9.
+    pub level: u32,
+    /// The base-2 logarithm of the window size, from 9 to 15.
+    pub window_bits: u8,
+}
+
+impl CompressionSettings {
+    /// The settings [`LinkState::encode`] uses. The maximum level and window
+    /// reproduce the links that ATO generates. They are set explicitly, so the
+    /// output does not change if the defaults do.
+    pub const DEFAULT: CompressionSettings = CompressionSettings {
+        level: 9,
+        window_bits: 15,
+    };
+}
+
+/// A link encoded with the settings that made it the shortest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BestEffortEncoding {
+    pub url: String,
+    pub settings: CompressionSettings,
+}
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("options are not supported by schema version 0")]
+    OptionsUnsupported,
+    #[error("program arguments are not supported by schema version 0")]
+    ProgramArgumentsUnsupported,
+}
+
+impl Display for SizeReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "MessagePack: {} bytes", self.raw_mp)?;
+        write!(f, "compressed:  {} bytes", self.compressed)?;
+        if self.raw_mp != 0 {
+            let ratio = self.compressed as f64 / self.raw_mp as f64;
+            write!(f, " ({:.0}%)", ratio * 100.0)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "base64:      {} bytes", self.base64)?;
+        write!(f, "URL:         {} bytes", self.url)
+    }
 }
 
 impl LinkState {
@@ -66,150 +179,631 @@ impl LinkState {
         LinkState::default()
     }
 
+    /// The state of a fresh ATO editor, so links built from it match what the
+    /// UI would produce from scratch. Unlike [`LinkState::default`], which
+    /// leaves every field empty, the encodings are `utf-8`, like ATO writes
+    /// them. The language is still empty, since ATO has the user choose one,
+    /// rather than starting with a default.
+    pub fn ato_ui_default() -> Self {
+        LinkState {
+            header_encoding: "utf-8".into(),
+            code_encoding: "utf-8".into(),
+            footer_encoding: "utf-8".into(),
+            input_encoding: "utf-8".into(),
+            ..LinkState::default()
+        }
+    }
+
+    /// Set the schema version, checking that no populated fields would be
+    /// dropped when encoding, since version 0 has no options or program
+    /// arguments.
+    pub fn set_schema(&mut self, schema: LinkSchema) -> Result<(), SchemaError> {
+        if schema == LinkSchema::V0 {
+            if !self.options.is_empty() {
+                return Err(SchemaError::OptionsUnsupported);
+            }
+            if !self.program_arguments.is_empty() {
+                return Err(SchemaError::ProgramArgumentsUnsupported);
+            }
+        }
+        self.schema = schema;
+        Ok(())
+    }
+
     /// Decode an Attempt This Online share link.
+    ///
+    /// A fragment, as in `?1=…#footer`, is discarded, since ATO only reads
+    /// the query string, so it is not kept when re-encoding.
     pub fn decode(url: &str) -> Result<Self, DecodeError> {
-        let (data, language) = LinkState::decode_url(url)?;
+        LinkState::decode_with(url, &DecodeOptions::default())
+    }
+
+    /// Decode an Attempt This Online share link, accepting only links exactly
+    /// as ATO generates them, with [`DecodeOptions::strict`]. Unlike
+    /// [`LinkState::decode`], a link without a scheme is an error.
+    pub fn decode_strict(url: &str) -> Result<Self, DecodeError> {
+        LinkState::decode_with(url, &DecodeOptions::strict())
+    }
+
+    /// Decode an Attempt This Online share link, with the given tolerance of
+    /// malformed links.
+    pub fn decode_with(url: &str, options: &DecodeOptions) -> Result<Self, DecodeError> {
+        LinkState::decode_parsed(
+            &parse_url(url, options)?,
+            options,
+            options.base64_mode(),
+            &mut DecodeReport::default(),
+        )
+    }
+
+    /// Decode an Attempt This Online share link, accepting only the base64
+    /// alphabets allowed by `mode`.
+    pub fn decode_with_base64_mode(url: &str, mode: Base64Mode) -> Result<Self, DecodeError> {
+        let options = DecodeOptions {
+            tolerate_junk_base64: mode == Base64Mode::Lenient,
+            ..DecodeOptions::default()
+        };
+        LinkState::decode_parsed(
+            &parse_url(url, &options)?,
+            &options,
+            mode,
+            &mut DecodeReport::default(),
+        )
+    }
+
+    /// Decode an Attempt This Online share link, which has already been
+    /// parsed.
+    pub fn decode_url_parsed(u: &Url) -> Result<Self, DecodeError> {
+        let options = DecodeOptions::default();
+        LinkState::decode_parsed(
+            u,
+            &options,
+            options.base64_mode(),
+            &mut DecodeReport::default(),
+        )
+    }
+
+    /// Decode an Attempt This Online share link, using the data with the
+    /// maximum schema version when there are several, like ATO does, instead
+    /// of returning `DecodeError::MultipleVersions`. For repeats of the same
+    /// version, the first is used.
+    pub fn decode_prefer_max(url: &str) -> Result<Self, DecodeError> {
+        let options = DecodeOptions {
+            prefer_max_schema: true,
+            ..DecodeOptions::default()
+        };
+        LinkState::decode_with(url, &options)
+    }
+
+    /// Decode an Attempt This Online share link, collecting unknown query keys
+    /// into `extra_query`, instead of returning `DecodeError::UnknownKey`, so
+    /// links with keys added by newer versions of ATO can be decoded and
+    /// re-encoded with them.
+    pub fn decode_permissive(url: &str) -> Result<Self, DecodeError> {
+        let options = DecodeOptions {
+            collect_extra_keys: true,
+            ..DecodeOptions::default()
+        };
+        LinkState::decode_with(url, &options)
+    }
+
+    /// Decode an Attempt This Online share link, also reporting which lenient
+    /// decoding paths it needed.
+    pub fn decode_with_report(url: &str) -> Result<(Self, DecodeReport), DecodeError> {
+        let options = DecodeOptions::default();
+        let mut report = DecodeReport::default();
+        let state = LinkState::decode_parsed(
+            &parse_url(url, &options)?,
+            &options,
+            options.base64_mode(),
+            &mut report,
+        )?;
+        Ok((state, report))
+    }
+
+    fn decode_parsed(
+        u: &Url,
+        options: &DecodeOptions,
+        mode: Base64Mode,
+        report: &mut DecodeReport,
+    ) -> Result<Self, DecodeError> {
+        match LinkState::decode_parsed_once(u, options, mode, report) {
+            // Only undo the extra layer when decoding fails, since a `%25` in
+            // a well-formed link is meant to be decoded once.
+            Err(err) if options.undo_double_encoding => {
+                let Some(u) = decode_query_layer(u) else {
+                    return Err(err);
+                };
+                let mut retry_report = DecodeReport::default();
+                let state = LinkState::decode_parsed_once(&u, options, mode, &mut retry_report)
+                    .map_err(|_| err)?;
+                *report = DecodeReport {
+                    undid_double_encoding: true,
+                    ..retry_report
+                };
+                Ok(state)
+            }
+            res => res,
+        }
+    }
+
+    fn decode_parsed_once(
+        u: &Url,
+        options: &DecodeOptions,
+        mode: Base64Mode,
+        report: &mut DecodeReport,
+    ) -> Result<Self, DecodeError> {
+        let (data, language, extra_query) = LinkState::decode_query(u, options, mode, report)?;
         let mut state = match data {
-            Some((schema, data)) => LinkState::deserialize_mp(schema, &*data)?,
+            // A link truncated to `?1=` has a schema, but no fields, so it is
+            // an empty state, rather than a decompression error.
+            Some((schema, data)) if data.is_empty() => LinkState {
+                schema,
+                ..LinkState::default()
+            },
+            Some((schema, data)) => LinkState::deserialize_with(schema, &data, options)?,
             None => LinkState::default(),
         };
         match language {
             Some(l) if state.language.is_empty() => state.language = l,
             _ => {}
         }
+        state.extra_query = extra_query;
         Ok(state)
     }
 
+    /// Reports whether two links have the same fields, ignoring the schema
+    /// version, so links that differ only in schema or in how their data was
+    /// compressed are equal. Unlike [`LinkState::semantically_eq`], the fields
+    /// are compared as written, so `""` and `utf-8` encodings differ.
+    ///
+    /// `==` compares the schema too, for exact equality.
+    pub fn content_eq(&self, other: &LinkState) -> bool {
+        LinkState {
+            schema: other.schema,
+            ..self.clone()
+        } == *other
+    }
+
     /// Encode an Attempt This Online share link.
+    ///
+    /// When only the language is set, the link has just the language in the
+    /// query string (e.g., `?L=python`), like ATO generates when a language is
+    /// chosen in an empty editor.
     pub fn encode(&self) -> Result<String, EncodeError> {
+        let mut s = String::new();
+        self.encode_into(&mut s)?;
+        Ok(s)
+    }
+
+    /// Encode an Attempt This Online share link, first normalizing the text
+    /// fields of a copy as `options` enables. See [`LinkState::encode`].
+    pub fn encode_with(&self, options: &EncodeOptions) -> Result<String, EncodeError> {
+        if !options.strip_bom && !options.strip_zero_width {
+            return self.encode();
+        }
+        let mut state = self.clone();
+        if options.strip_bom {
+            state.strip_bom();
+        }
+        if options.strip_zero_width {
+            state.strip_zero_width();
+        }
+        state.encode()
+    }
+
+    /// Encode an Attempt This Online share link as a parsed URL, for callers
+    /// that modify it further. See [`LinkState::encode`].
+    pub fn encode_url(&self) -> Result<Url, EncodeError> {
+        Ok(Url::parse(&self.encode()?)?)
+    }
+
+    /// Encode an Attempt This Online share link, writing it to `w`, without
+    /// allocating the URL. See [`LinkState::encode`].
+    pub fn encode_into<W: fmt::Write>(&self, w: &mut W) -> Result<(), EncodeError> {
+        w.write_str(RUN_URL)?;
+        if self.is_language_only() {
+            w.write_str("?L=")?;
+            for s in form_urlencoded::byte_serialize(self.language.as_bytes()) {
+                w.write_str(s)?;
+            }
+        } else {
+            let (schema, data) = self.encode_data()?;
+            LinkState::encode_query(w, schema, &data)?;
+        }
+        self.encode_extra_query(w)
+    }
+
+    /// Encode an Attempt This Online share link, trying up to `attempts`
+    /// compression settings and keeping the shortest, for golfers who want
+    /// the shortest link. The settings which produced it are returned, so it
+    /// can be reproduced. With one attempt, this is the same as
+    /// [`LinkState::encode`]. Each attempt compresses the data again.
+    pub fn encode_best_effort(&self, attempts: usize) -> Result<BestEffortEncoding, EncodeError> {
+        if self.is_language_only() {
+            return Ok(BestEffortEncoding {
+                url: self.encode()?,
+                settings: CompressionSettings::DEFAULT,
+            });
+        }
+        let mp = self.serialize_mp()?;
+        let mut best: Option<(Vec<u8>, CompressionSettings)> = None;
+        for &settings in BEST_EFFORT_SETTINGS.iter().take(attempts.max(1)) {
+            let compressed = compress(&mp, settings)?;
+            if best
+                .as_ref()
+                .is_none_or(|(b, _)| compressed.len() < b.len())
+            {
+                best = Some((compressed, settings));
+            }
+        }
+        let (compressed, settings) = best.unwrap();
+        let mut url = RUN_URL.to_owned();
+        LinkState::encode_query(&mut url, self.schema, &URL_SAFE_NO_PAD.encode(compressed))?;
+        self.encode_extra_query(&mut url)?;
+        Ok(BestEffortEncoding { url, settings })
+    }
+
+    /// The MessagePack data of the link, before it is compressed, for
+    /// comparing with [`LinkState::decode_raw`] to check whether two links
+    /// differ only in compression.
+    pub fn encoded_mp(&self) -> Result<Vec<u8>, EncodeError> {
+        self.serialize_mp()
+    }
+
+    /// Decode the data of an Attempt This Online share link to its
+    /// decompressed MessagePack and schema, without deserializing it. Returns
+    /// `None` when the link has no data, like `?L=python`.
+    pub fn decode_raw(url: &str) -> Result<Option<(LinkSchema, Vec<u8>)>, DecodeError> {
+        let options = DecodeOptions::default();
+        let (data, _, _) = LinkState::decode_query(
+            &parse_url(url, &options)?,
+            &options,
+            options.base64_mode(),
+            &mut DecodeReport::default(),
+        )?;
+        Ok(data)
+    }
+
+    /// Encode just the data of an Attempt This Online share link, without the
+    /// URL or the `1=` key, for storing links compactly. The schema is returned
+    /// separately, since it is encoded in the key. Unlike
+    /// [`LinkState::encode`], the data is always written, even when only the
+    /// language is set.
+    pub fn encode_data(&self) -> Result<(LinkSchema, String), EncodeError> {
+        let mp = self.serialize_mp()?;
+        let compressed = compress(&mp, CompressionSettings::DEFAULT)?;
+        Ok((self.schema, URL_SAFE_NO_PAD.encode(compressed)))
+    }
+
+    /// Decode the data of an Attempt This Online share link, as returned by
+    /// [`LinkState::encode_data`].
+    pub fn decode_data(schema: LinkSchema, data: &str) -> Result<Self, DecodeError> {
+        let options = DecodeOptions::default();
+        let data = decode_data_bytes(
+            data,
+            &options,
+            options.base64_mode(),
+            &mut DecodeReport::default(),
+        )?;
+        LinkState::deserialize_mp(schema, &data, false)
+    }
+
+    /// Write the extra query keys, which follow the data or language.
+    fn encode_extra_query<W: fmt::Write>(&self, w: &mut W) -> Result<(), EncodeError> {
+        for (key, value) in &self.extra_query {
+            w.write_char('&')?;
+            for s in form_urlencoded::byte_serialize(key.as_bytes()) {
+                w.write_str(s)?;
+            }
+            w.write_char('=')?;
+            for s in form_urlencoded::byte_serialize(value.as_bytes()) {
+                w.write_str(s)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Report the size of the link at each stage of encoding, to show where
+    /// its length comes from.
+    pub fn size_report(&self) -> Result<SizeReport, EncodeError> {
+        if self.is_language_only() {
+            return Ok(SizeReport {
+                url: self.encode()?.len(),
+                ..SizeReport::default()
+            });
+        }
         let mp = self.serialize_mp()?;
-        LinkState::encode_url(self.schema, &*mp, Compression::best())
+        let compressed = compress(&mp, CompressionSettings::DEFAULT)?;
+        let base64 = base64::encoded_len(compressed.len(), false).unwrap();
+        let mut extra = String::new();
+        self.encode_extra_query(&mut extra)?;
+        Ok(SizeReport {
+            raw_mp: mp.len(),
+            compressed: compressed.len(),
+            base64,
+            url: RUN_URL.len() + "?1=".len() + base64 + extra.len(),
+        })
+    }
+
+    /// Encode the shortest possible link, with just the language in the query
+    /// string (e.g., `?L=python`), like ATO generates when a language is
+    /// chosen in an empty editor. Returns `None` when any other field is set,
+    /// including encodings, since they would be lost.
+    pub fn encode_language_only(&self) -> Option<String> {
+        if self.is_language_only() {
+            self.encode().ok()
+        } else {
+            None
+        }
+    }
+
+    /// Reports whether the language is the only field set. The schema is not
+    /// considered, since it is not encoded in that case.
+    pub(crate) fn is_language_only(&self) -> bool {
+        !self.language.is_empty()
+            && [
+                &self.options,
+                &self.header,
+                &self.header_encoding,
+                &self.code,
+                &self.code_encoding,
+                &self.footer,
+                &self.footer_encoding,
+                &self.program_arguments,
+                &self.input,
+                &self.input_encoding,
+            ]
+            .iter()
+            .all(|field| field.is_empty())
+    }
+
+    /// Convert `\r\n` and `\r` line endings to `\n` in the header, code,
+    /// footer, and input, like the textareas in the ATO frontend do, for code
+    /// pasted from Windows tools. Fields encoded as base64 are left unchanged,
+    /// since they are not text.
+    ///
+    /// This is not done by [`LinkState::encode`], so encoding is lossless by
+    /// default. When it is called before encoding, decoding the link does not
+    /// return the original fields.
+    pub fn normalize_newlines(&mut self) {
+        for text in self.text_fields_mut() {
+            if text.contains('\r') {
+                *text = text.replace("\r\n", "\n").replace('\r', "\n");
+            }
+        }
+    }
+
+    /// Remove a leading byte order mark (U+FEFF) from the header, code,
+    /// footer, and input, which editors on Windows may insert when code is
+    /// copied. Fields encoded as base64 are left unchanged.
+    ///
+    /// Encoding only does this with [`EncodeOptions::strip_bom`], since
+    /// decoding the link afterwards does not return the original fields.
+    pub fn strip_bom(&mut self) {
+        for text in self.text_fields_mut() {
+            if let Some(rest) = text.strip_prefix('\u{feff}') {
+                *text = rest.to_owned();
+            }
+        }
+    }
+
+    /// Remove zero-width characters (U+200B, U+200C, U+200D, U+2060, and
+    /// U+FEFF) anywhere in the header, code, footer, and input, which are
+    /// invisible in an editor, but change the program. Fields encoded as
+    /// base64 are left unchanged.
+    ///
+    /// Joiners are significant in some text, like emoji sequences, so only
+    /// use this for code that should not contain them. Encoding only does this
+    /// with [`EncodeOptions::strip_zero_width`].
+    pub fn strip_zero_width(&mut self) {
+        let is_zero_width = |c| matches!(c, '\u{200b}'..='\u{200d}' | '\u{2060}' | '\u{feff}');
+        for text in self.text_fields_mut() {
+            if text.contains(is_zero_width) {
+                text.retain(|c| !is_zero_width(c));
+            }
+        }
+    }
+
+    /// The header, code, footer, and input, excluding those encoded as base64,
+    /// which are not text.
+    fn text_fields_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        [
+            (&mut self.header, &self.header_encoding),
+            (&mut self.code, &self.code_encoding),
+            (&mut self.footer, &self.footer_encoding),
+            (&mut self.input, &self.input_encoding),
+        ]
+        .into_iter()
+        .filter(|(_, encoding)| *encoding != "base64")
+        .map(|(text, _)| text)
+    }
+
+    /// The header, truncated to at most `max` characters.
+    pub fn header_preview(&self, max: usize) -> &str {
+        truncate_chars(&self.header, max)
+    }
+
+    /// The code, truncated to at most `max` characters.
+    pub fn code_preview(&self, max: usize) -> &str {
+        truncate_chars(&self.code, max)
+    }
+
+    /// The footer, truncated to at most `max` characters.
+    pub fn footer_preview(&self, max: usize) -> &str {
+        truncate_chars(&self.footer, max)
+    }
+
+    /// The input, truncated to at most `max` characters.
+    pub fn input_preview(&self, max: usize) -> &str {
+        truncate_chars(&self.input, max)
     }
 
     /// Decode and decompress an Attempt This Online share link.
-    fn decode_url(
-        url: &str,
-    ) -> Result<(Option<(LinkSchema, Vec<u8>)>, Option<String>), DecodeError> {
-        let u = Url::parse(url).map_err(DecodeError::Url)?;
+    fn decode_query(
+        u: &Url,
+        options: &DecodeOptions,
+        mode: Base64Mode,
+        report: &mut DecodeReport,
+    ) -> Result<DecodedUrl, DecodeError> {
         let mut data = None;
         let mut language = None;
-        for (key, value) in u.query_pairs() {
+        let mut extra = Vec::new();
+        for (key, value) in query_pairs(u) {
             let schema = match &*key {
                 "0" => LinkSchema::V0,
                 "1" => LinkSchema::V1,
                 "L" | "l" => {
-                    if language.is_some() {
-                        return Err(DecodeError::MultipleLanguages);
-                    }
                     // See https://github.com/attempt-this-online/attempt-this-online/blob/b694efd9cfaea87d93827e33ec7f5d812a431833/frontend/pages/run.tsx#L237-L269
-                    language = Some(value.into_owned());
+                    match &language {
+                        // Repeated keys are harmless when they agree
+                        Some(l) if *l == value => {}
+                        Some(_) => return Err(DecodeError::MultipleLanguages),
+                        None => language = Some(value.into_owned()),
+                    }
+                    continue;
+                }
+                _ if options.collect_extra_keys => {
+                    extra.push((key.into_owned(), value.into_owned()));
                     continue;
                 }
                 _ => return Err(DecodeError::UnknownKey(key.into_owned())),
             };
-            if data.is_some() {
+            match data {
                 // ATO chooses the maximum schema version, when multiple are
-                // provided, but that should never be generated.
-                return Err(DecodeError::MultipleVersions);
-            }
-            data = Some((schema, value));
-        }
-        let data = if let Some((schema, data)) = data {
-            // ATO's base64 decoding allows the URL-safe and standard alphabets,
-            // even with `+` and `-` or `/` and `_` intermixed. Any characters
-            // outside those alphabets, including `=`, are removed before
-            // decoding. See toUint8Array in https://github.com/dankogai/js-base64/blob/34cd9344dae428adbde8084e28339a591bbdf7e5/base64.ts#L201
-            let compressed = match URL_SAFE_NO_PAD.decode(&*data) {
-                Ok(data) => data,
-                Err(err) => {
-                    // Since few links have invalid characters, this tries a
-                    // strict URL-safe decode first. The standard alphabet
-                    // characters are left, so decoding will fail, as it most
-                    // likely indicates a malformed link.
-                    lazy_static! {
-                        static ref TIDY: Regex = Regex::new(r"[^A-Za-z0-9+/\-_]+").unwrap();
+                // provided, but that should never be generated. See
+                // https://github.com/attempt-this-online/attempt-this-online/blob/b694efd9cfaea87d93827e33ec7f5d812a431833/frontend/pages/run.tsx#L237-L269
+                Some((prev, _)) if options.prefer_max_schema => {
+                    if schema > prev {
+                        data = Some((schema, value));
                     }
-                    let data = TIDY.replace_all(data.as_bytes(), &b""[..]);
-                    URL_SAFE_NO_PAD.decode(data).map_err(|_| err)?
                 }
-            };
-
-            let mut buf = Vec::new();
-            DeflateDecoder::new(&*compressed).read_to_end(&mut buf)?;
-            Some((schema, buf))
-        } else {
-            None
+                Some((prev, _)) => return Err(DecodeError::MultipleVersions(prev, schema)),
+                None => data = Some((schema, value)),
+            }
+        }
+        let data = match data {
+            // Empty data is not valid DEFLATE, so it is handled separately
+            Some((schema, data)) if data.is_empty() => Some((schema, Vec::new())),
+            Some((schema, data)) => {
+                Some((schema, decode_data_bytes(&data, options, mode, report)?))
+            }
+            None => None,
         };
-        Ok((data, language))
+        Ok((data, language, extra))
     }
 
-    /// Encode and compress an Attempt This Online share link.
-    fn encode_url<R: BufRead>(
+    /// Write the query string for the encoded data of an Attempt This Online
+    /// share link.
+    fn encode_query<W: fmt::Write>(
+        w: &mut W,
         schema: LinkSchema,
-        r: R,
-        level: Compression,
-    ) -> Result<String, EncodeError> {
-        let mut z = DeflateEncoder::new(r, level);
-        let mut d = Vec::new();
-        z.read_to_end(&mut d)?;
-        let mut b = URL_SAFE_NO_PAD.encode(&d);
+        data: &str,
+    ) -> Result<(), EncodeError> {
+        // The URL-safe base64 alphabet needs no escaping in a query string
         match schema {
-            LinkSchema::V0 => b.insert_str(0, "0="),
-            LinkSchema::V1 => b.insert_str(0, "1="),
+            LinkSchema::V0 => w.write_str("?0=")?,
+            LinkSchema::V1 => w.write_str("?1=")?,
         }
-        let mut u = Url::parse(RUN_URL).unwrap();
-        u.set_query(Some(&b));
-        Ok(u.to_string())
+        w.write_str(data)?;
+        Ok(())
     }
 
-    /// Deserialize from MessagePack format.
-    fn deserialize_mp(schema: LinkSchema, data: &[u8]) -> Result<Self, DecodeError> {
-        match schema {
-            LinkSchema::V0 => {
-                let data: [String; 9] = rmp_serde::from_read(data)?;
-                let [language, header, header_encoding, code, code_encoding, footer, footer_encoding, input, input_encoding] =
-                    data;
-                Ok(LinkState {
-                    schema,
-                    language,
-                    options: String::new(),
-                    header,
-                    header_encoding,
-                    code,
-                    code_encoding,
-                    footer,
-                    footer_encoding,
-                    program_arguments: String::new(),
-                    input,
-                    input_encoding,
-                })
+    /// Deserialize from MessagePack format, retrying with the fallbacks
+    /// enabled in `options` when the data does not match its schema.
+    fn deserialize_with(
+        schema: LinkSchema,
+        data: &[u8],
+        options: &DecodeOptions,
+    ) -> Result<Self, DecodeError> {
+        let err = match LinkState::deserialize_mp(schema, data, false) {
+            Ok(state) => return Ok(state),
+            Err(err) => err,
+        };
+        if options.compat_layouts {
+            let other = match schema {
+                LinkSchema::V0 => LinkSchema::V1,
+                LinkSchema::V1 => LinkSchema::V0,
+            };
+            if let Ok(state) = LinkState::deserialize_mp(other, data, false) {
+                return Ok(state);
+            }
+        }
+        if options.tolerate_field_count {
+            if let Ok(state) = LinkState::deserialize_mp(schema, data, true) {
+                return Ok(state);
             }
-            LinkSchema::V1 => {
-                let data: [String; 11] = rmp_serde::from_read(data)?;
-                let [language, options, header, header_encoding, code, code_encoding, footer, footer_encoding, program_arguments, input, input_encoding] =
-                    data;
-                Ok(LinkState {
+        }
+        Err(err)
+    }
+
+    /// Deserialize from MessagePack format with the layout of the schema. When
+    /// `any_count`, fields past those of the schema are ignored and missing
+    /// trailing fields are empty; otherwise, the array must have exactly the
+    /// fields of the schema.
+    fn deserialize_mp(
+        schema: LinkSchema,
+        data: &[u8],
+        any_count: bool,
+    ) -> Result<Self, DecodeError> {
+        let expected = match schema {
+            LinkSchema::V0 => 9,
+            LinkSchema::V1 => 11,
+        };
+        let mut de = rmp_serde::Deserializer::new(data);
+        let (mut fields, found) = WireFields(expected).deserialize(&mut de)?;
+        // The most common corruption is an array with the wrong number of
+        // fields, for which rmp_serde would only report "invalid length".
+        if found != expected {
+            if !any_count {
+                return Err(DecodeError::SchemaMismatch {
                     schema,
-                    language,
-                    options,
-                    header,
-                    header_encoding,
-                    code,
-                    code_encoding,
-                    footer,
-                    footer_encoding,
-                    program_arguments,
-                    input,
-                    input_encoding,
-                })
+                    expected,
+                    found,
+                });
             }
+            fields.resize(expected, String::new());
         }
+        let mut fields = fields.into_iter();
+        let mut next = || fields.next().unwrap();
+        Ok(match schema {
+            LinkSchema::V0 => LinkState {
+                schema,
+                language: next(),
+                options: String::new(),
+                header: next(),
+                header_encoding: next(),
+                code: next(),
+                code_encoding: next(),
+                footer: next(),
+                footer_encoding: next(),
+                program_arguments: String::new(),
+                input: next(),
+                input_encoding: next(),
+                extra_query: Vec::new(),
+            },
+            LinkSchema::V1 => LinkState {
+                schema,
+                language: next(),
+                options: next(),
+                header: next(),
+                header_encoding: next(),
+                code: next(),
+                code_encoding: next(),
+                footer: next(),
+                footer_encoding: next(),
+                program_arguments: next(),
+                input: next(),
+                input_encoding: next(),
+                extra_query: Vec::new(),
+            },
+        })
     }
 
     /// Serialize as MessagePack format.
     fn serialize_mp(&self) -> Result<Vec<u8>, EncodeError> {
         match self.schema {
-            LinkSchema::V0 => Ok(rmp_serde::to_vec(&[
+            LinkSchema::V0 => Ok(rmp_serde::to_vec(&WireV0(
                 &self.language,
                 &self.header,
                 &self.header_encoding,
@@ -219,8 +813,8 @@ impl LinkState {
                 &self.footer_encoding,
                 &self.input,
                 &self.input_encoding,
-            ])?),
-            LinkSchema::V1 => Ok(rmp_serde::to_vec(&[
+            ))?),
+            LinkSchema::V1 => Ok(rmp_serde::to_vec(&WireV1(
                 &self.language,
                 &self.options,
                 &self.header,
@@ -232,13 +826,482 @@ impl LinkState {
                 &self.program_arguments,
                 &self.input,
                 &self.input_encoding,
-            ])?),
+            ))?),
         }
     }
 }
 
+/// The MessagePack array for schema version 0. Its fields are, in order:
+/// language, header, header encoding, code, code encoding, footer, footer
+/// encoding, input, and input encoding.
+#[derive(Serialize)]
+struct WireV0<S>(S, S, S, S, S, S, S, S, S);
+
+/// The MessagePack array for schema version 1. Its fields are, in order:
+/// language, options, header, header encoding, code, code encoding, footer,
+/// footer encoding, program arguments, input, and input encoding.
+#[derive(Serialize)]
+struct WireV1<S>(S, S, S, S, S, S, S, S, S, S, S);
+
+/// Deserializes the string fields of a MessagePack array, up to the given
+/// number, and the total number of fields. Fields past that number are
+/// ignored, whatever their type.
+struct WireFields(usize);
+
+impl<'de> DeserializeSeed<'de> for WireFields {
+    type Value = (Vec<String>, usize);
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for WireFields {
+    type Value = (Vec<String>, usize);
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "an array of {} strings", self.0)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut fields = Vec::with_capacity(self.0);
+        while fields.len() < self.0 {
+            match seq.next_element()? {
+                Some(field) => fields.push(field),
+                None => break,
+            }
+        }
+        let mut found = fields.len();
+        while seq.next_element::<IgnoredAny>()?.is_some() {
+            found += 1;
+        }
+        Ok((fields, found))
+    }
+}
+
+/// Check that each link in a newline-separated list decodes and re-encodes to
+/// the same data.
+pub fn check_roundtrip(links: &str) -> CorpusReport {
+    let mut report = CorpusReport::default();
+    for link in links.lines() {
+        report.total += 1;
+        match check_link(link) {
+            Ok(true) => report.exact += 1,
+            Ok(false) => report.encoding_differs += 1,
+            Err(reason) => report.failures.push(CorpusFailure {
+                link: link.into(),
+                reason,
+            }),
+        }
+    }
+    report
+}
+
+/// Roundtrip a link and return whether it re-encoded exactly.
+fn check_link(link: &str) -> Result<bool, String> {
+    let state = LinkState::decode(link).map_err(|err| format!("decoding: {err}"))?;
+    let encoded = state.encode().map_err(|err| format!("encoding: {err}"))?;
+    if encoded == link {
+        return Ok(true);
+    }
+    let options = DecodeOptions::default();
+    let (data, language, _) = LinkState::decode_query(
+        &parse_url(link, &options).unwrap(),
+        &options,
+        Base64Mode::default(),
+        &mut DecodeReport::default(),
+    )
+    .unwrap();
+    if let Some((_, decoded_raw)) = data {
+        let encoded_raw = state.serialize_mp().unwrap();
+        if decoded_raw != encoded_raw {
+            return Err("MessagePack data differs".into());
+        }
+    }
+    if let Some(l) = language {
+        if state.language != l {
+            return Err(format!("language changed to `{}`", state.language));
+        }
+    }
+    // The re-encoded link may use another schema or compression, but must
+    // have the same fields
+    let reencoded = LinkState::decode(&encoded).map_err(|err| format!("re-decoding: {err}"))?;
+    if !reencoded.content_eq(&state) {
+        return Err("fields differ after re-encoding".into());
+    }
+    Ok(false)
+}
+
+/// How a link differs from the link it re-encodes to, for auditing why links
+/// do not roundtrip byte-for-byte.
+#[derive(Debug)]
+pub enum Diagnosis {
+    /// The link re-encodes to exactly the same URL.
+    Exact,
+    /// The query string has the same keys and values, but in another order.
+    QueryOrder,
+    /// The data is compressed the same, but its base64 uses the standard
+    /// alphabet or has padding or other junk characters.
+    Base64Alphabet,
+    /// The data is the same MessagePack, but compressed differently.
+    Compression,
+    /// The fields are the same, but serialized to different MessagePack, e.g.,
+    /// with wider string headers than needed.
+    Serialization,
+    /// The data is the same, but the URL differs elsewhere, e.g., in its
+    /// scheme, its escaping, a redundant `L` key, or a fragment.
+    Url,
+    /// The re-encoded link does not decode to the same fields.
+    ContentMismatch,
+    /// The link could not be decoded.
+    DecodeFailed(DecodeError),
+    /// The decoded link could not be re-encoded.
+    EncodeFailed(EncodeError),
+}
+
+/// Decode a link, re-encode it, and report how the two differ. Unknown query
+/// keys are kept, like [`LinkState::decode_permissive`], so links that differ
+/// only in the order of their keys can be told apart.
+pub fn diagnose(url: &str) -> Diagnosis {
+    let options = DecodeOptions {
+        collect_extra_keys: true,
+        ..DecodeOptions::default()
+    };
+    let state = match LinkState::decode_with(url, &options) {
+        Ok(state) => state,
+        Err(err) => return Diagnosis::DecodeFailed(err),
+    };
+    let encoded = match state.encode() {
+        Ok(encoded) => encoded,
+        Err(err) => return Diagnosis::EncodeFailed(err),
+    };
+    if encoded == url {
+        return Diagnosis::Exact;
+    }
+    match LinkState::decode_with(&encoded, &options) {
+        Ok(reencoded) if reencoded.content_eq(&state) => {}
+        _ => return Diagnosis::ContentMismatch,
+    }
+
+    // Both URLs parse, since they decoded
+    let (Ok(u), Ok(encoded)) = (parse_url(url, &options), parse_url(&encoded, &options)) else {
+        return Diagnosis::Url;
+    };
+    let pairs: Vec<_> = query_pairs(&u).collect();
+    let encoded_pairs: Vec<_> = query_pairs(&encoded).collect();
+    if pairs != encoded_pairs {
+        let (mut sorted, mut encoded_sorted) = (pairs.clone(), encoded_pairs.clone());
+        sorted.sort();
+        encoded_sorted.sort();
+        if sorted == encoded_sorted {
+            return Diagnosis::QueryOrder;
+        }
+    }
+    let mp = |u: &Url| {
+        let (data, _, _) = LinkState::decode_query(
+            u,
+            &options,
+            options.base64_mode(),
+            &mut DecodeReport::default(),
+        )
+        .ok()?;
+        data
+    };
+    if mp(&u) != mp(&encoded) {
+        return Diagnosis::Serialization;
+    }
+    let data = |pairs: &[(Cow<'_, str>, Cow<'_, str>)]| {
+        pairs
+            .iter()
+            .find(|(key, _)| key == "0" || key == "1")
+            .map(|(_, value)| value.to_string())
+    };
+    let (data, encoded_data) = (data(&pairs), data(&encoded_pairs));
+    if data == encoded_data {
+        return Diagnosis::Url;
+    }
+    // Translate to the URL-safe alphabet and remove junk, like decoding does
+    let url_safe = data.map(|data| {
+        data.chars()
+            .filter_map(|c| match c {
+                '+' => Some('-'),
+                '/' => Some('_'),
+                'A'..='Z' | 'a'..='z' | '0'..='9' | '-' | '_' => Some(c),
+                _ => None,
+            })
+            .collect::<String>()
+    });
+    if url_safe == encoded_data {
+        Diagnosis::Base64Alphabet
+    } else {
+        Diagnosis::Compression
+    }
+}
+
+/// Decode the base64 data of an Attempt This Online share link and decompress
+/// it.
+fn decode_data_bytes(
+    data: &str,
+    options: &DecodeOptions,
+    mode: Base64Mode,
+    report: &mut DecodeReport,
+) -> Result<Vec<u8>, DecodeError> {
+    // ATO's base64 decoding allows the URL-safe and standard alphabets,
+    // even with `+` and `-` or `/` and `_` intermixed. Any characters
+    // outside those alphabets, including `=`, are removed before
+    // decoding. See toUint8Array in https://github.com/dankogai/js-base64/blob/34cd9344dae428adbde8084e28339a591bbdf7e5/base64.ts#L201
+    // Only the lenient options match that.
+    let fast = if options.tolerate_junk_base64 {
+        // Padding is common in copied links, so strip it before the
+        // first decode, rather than leaving it to the slow path.
+        data.trim_end_matches('=')
+    } else {
+        data
+    };
+    let compressed = match mode.decode(fast.as_bytes(), report) {
+        Ok(data) => data,
+        Err(err) if options.tolerate_junk_base64 => {
+            // Since few links have other invalid characters, this tries
+            // a decode without removing them first.
+            lazy_static! {
+                static ref TIDY: Regex = Regex::new(r"[^A-Za-z0-9+/\-_]+").unwrap();
+            }
+            let tidy = TIDY.replace_all(data.as_bytes(), &b""[..]);
+            let compressed = mode.decode(&tidy, report).map_err(|_| err)?;
+            report.used_base64_fallback = true;
+            report.stripped_bytes = data.len() - tidy.len();
+            compressed
+        }
+        Err(err) => return Err(err.into()),
+    };
+    decompress(
+        &compressed,
+        options.max_decompressed_size,
+        options.on_decompress.as_ref(),
+    )
+}
+
+/// Parse an ATO link, first removing whitespace from its query string, when
+/// enabled.
+fn parse_url(url: &str, options: &DecodeOptions) -> Result<Url, url::ParseError> {
+    if options.join_wrapped_lines {
+        if let Some((base, query)) = url.trim().split_once('?') {
+            if base.ends_with("ato.pxeger.com/run") && query.contains(char::is_whitespace) {
+                let query: String = query.split_whitespace().collect();
+                return options.parse_url(&format!("{base}?{query}"));
+            }
+        }
+    }
+    options.parse_url(url)
+}
+
+/// Split the query string of an ATO link into decoded keys and values, like
+/// [`Url::query_pairs`], except that `+` is kept in the data, instead of
+/// being decoded as a space. Base64 data with the standard alphabet may have
+/// an unescaped `+`, which would otherwise be lost.
+fn query_pairs(u: &Url) -> impl Iterator<Item = (Cow<'_, str>, Cow<'_, str>)> {
+    fn decode(s: &str, plus_is_space: bool) -> Cow<'_, str> {
+        if plus_is_space && s.contains('+') {
+            Cow::Owned(
+                percent_decode_str(&s.replace('+', " "))
+                    .decode_utf8_lossy()
+                    .into_owned(),
+            )
+        } else {
+            percent_decode_str(s).decode_utf8_lossy()
+        }
+    }
+    u.query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let key = decode(key, true);
+            let is_data = key == "0" || key == "1";
+            (key, decode(value, !is_data))
+        })
+}
+
+/// Truncates the text to at most `max` chars. It cuts only at char boundaries,
+/// but may split a grapheme cluster.
+fn truncate_chars(s: &str, max: usize) -> &str {
+    match s.char_indices().nth(max) {
+        Some((i, _)) => &s[..i],
+        None => s,
+    }
+}
+
+/// Remove one layer of percent-encoding from the query string of a link, if
+/// it has any encoded `%`.
+fn decode_query_layer(u: &Url) -> Option<Url> {
+    let query = u.query()?;
+    if !query.contains("%25") {
+        return None;
+    }
+    let decoded = percent_decode_str(query).decode_utf8().ok()?;
+    let mut u = u.clone();
+    u.set_query(Some(&decoded));
+    Some(u)
+}
+
+/// Settings tried by [`LinkState::encode_best_effort`], in order.
+const BEST_EFFORT_SETTINGS: &[CompressionSettings] = &[
+    CompressionSettings::DEFAULT,
+    CompressionSettings {
+        level: 8,
+        window_bits: 15,
+    },
+    CompressionSettings {
+        level: 7,
+        window_bits: 15,
+    },
+    CompressionSettings {
+        level: 6,
+        window_bits: 15,
+    },
+    CompressionSettings {
+        level: 5,
+        window_bits: 15,
+    },
+    CompressionSettings {
+        level: 4,
+        window_bits: 15,
+    },
+    CompressionSettings {
+        level: 3,
+        window_bits: 15,
+    },
+    CompressionSettings {
+        level: 2,
+        window_bits: 15,
+    },
+    CompressionSettings {
+        level: 1,
+        window_bits: 15,
+    },
+    CompressionSettings {
+        level: 9,
+        window_bits: 14,
+    },
+    CompressionSettings {
+        level: 9,
+        window_bits: 12,
+    },
+    CompressionSettings {
+        level: 9,
+        window_bits: 10,
+    },
+];
+
+/// Compress to raw DEFLATE data, as ATO generates with
+/// [`CompressionSettings::DEFAULT`], using the given level and window size.
+/// The strategy cannot be set through flate2 and is always the zlib default.
+fn compress(data: &[u8], settings: CompressionSettings) -> io::Result<Vec<u8>> {
+    let mut compress = Compress::new_with_window_bits(
+        Compression::new(settings.level),
+        false,
+        settings.window_bits,
+    );
+    let mut compressed = Vec::with_capacity(data.len() / 2 + 64);
+    loop {
+        let rest = &data[compress.total_in() as usize..];
+        match compress.compress_vec(rest, &mut compressed, FlushCompress::Finish)? {
+            Status::StreamEnd => return Ok(compressed),
+            Status::Ok | Status::BufError => compressed.reserve(compressed.capacity()),
+        }
+    }
+}
+
+/// Decompress raw DEFLATE data, as ATO generates, up to `max_len` bytes. Some
+/// third-party tools wrap the data with gzip instead, so that is accepted as a
+/// fallback. The progress is reported to `on_decompress`, which can cancel it.
+fn decompress(
+    compressed: &[u8],
+    max_len: u64,
+    on_decompress: Option<&ProgressCallback>,
+) -> Result<Vec<u8>, DecodeError> {
+    let mut buf = Vec::new();
+    let mut reader = Progress::new(
+        DeflateDecoder::new(compressed).take(max_len + 1),
+        on_decompress,
+    );
+    let res = reader.read_to_end(&mut buf);
+    match res {
+        Ok(_) => {}
+        Err(_) if reader.cancelled => return Err(DecodeError::Cancelled(reader.read)),
+        // The gzip and zlib headers are almost never valid raw DEFLATE (gzip
+        // never is, since it starts with a reserved block type), so they are
+        // only checked once decompression fails.
+        Err(_) if compressed.starts_with(&[0x1f, 0x8b]) => {
+            buf.clear();
+            let mut reader =
+                Progress::new(GzDecoder::new(compressed).take(max_len + 1), on_decompress);
+            if let Err(err) = reader.read_to_end(&mut buf) {
+                if reader.cancelled {
+                    return Err(DecodeError::Cancelled(reader.read));
+                }
+                return Err(err.into());
+            }
+        }
+        Err(_) if is_zlib_header(compressed) => return Err(DecodeError::UnexpectedZlibHeader),
+        Err(err) => return Err(err.into()),
+    }
+    if buf.len() as u64 > max_len {
+        return Err(DecodeError::TooLarge(max_len));
+    }
+    Ok(buf)
+}
+
+/// A reader that reports the number of bytes read so far to a callback, which
+/// can cancel reading by returning `false`.
+struct Progress<'a, R> {
+    inner: R,
+    on_read: Option<&'a ProgressCallback>,
+    read: u64,
+    cancelled: bool,
+}
+
+impl<'a, R: Read> Progress<'a, R> {
+    fn new(inner: R, on_read: Option<&'a ProgressCallback>) -> Self {
+        Progress {
+            inner,
+            on_read,
+            read: 0,
+            cancelled: false,
+        }
+    }
+}
+
+impl<R: Read> Read for Progress<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if let Some(on_read) = self.on_read {
+            if n != 0 && !on_read.call(self.read) {
+                self.cancelled = true;
+                return Err(io::Error::other("cancelled"));
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Reports whether the data starts with a zlib header (RFC 1950) for DEFLATE.
+fn is_zlib_header(data: &[u8]) -> bool {
+    match data {
+        [cmf, flg, ..] => {
+            cmf & 0x0f == 8 && cmf >> 4 <= 7 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0
+        }
+        _ => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use base64::Engine;
+    use flate2::bufread::{DeflateEncoder, GzEncoder, ZlibEncoder};
+
     use super::*;
 
     #[test]
@@ -259,8 +1322,24 @@ mod tests {
             program_arguments: "".into(),
             input: "".into(),
             input_encoding: "utf-8".into(),
+            extra_query: Vec::new(),
         };
         assert_eq!(state, LinkState::decode(url).unwrap());
+        assert_eq!(
+            state,
+            LinkState::decode_url_parsed(&Url::parse(url).unwrap()).unwrap(),
+        );
+        // V0 serializes all nine fields, even when empty, like ATO did
+        let (data, _, _) = LinkState::decode_query(
+            &Url::parse(url).unwrap(),
+            &DecodeOptions::default(),
+            Base64Mode::default(),
+            &mut DecodeReport::default(),
+        )
+        .unwrap();
+        let (schema, raw) = data.unwrap();
+        assert_eq!(schema, LinkSchema::V0);
+        assert_eq!(raw, state.serialize_mp().unwrap());
         assert_eq!(url, state.encode().unwrap());
     }
 
@@ -282,6 +1361,7 @@ mod tests {
             program_arguments: "[\"1\",\"2\"]".into(),
             input: "1\n2\n1".into(),
             input_encoding: "utf-8".into(),
+            extra_query: Vec::new(),
         };
         assert_eq!(state, LinkState::decode(url).unwrap());
         assert_eq!(url, state.encode().unwrap());
@@ -302,6 +1382,7 @@ mod tests {
             program_arguments: "[\"Hello World\"]".into(),
             input: "".into(),
             input_encoding: "utf-8".into(),
+            extra_query: Vec::new(),
         };
         assert_eq!(state, LinkState::decode(url).unwrap());
         assert_eq!(url, state.encode().unwrap());
@@ -322,6 +1403,7 @@ mod tests {
             program_arguments: "".into(),
             input: "".into(),
             input_encoding: "utf-8".into(),
+            extra_query: Vec::new(),
         };
         assert_eq!(state, LinkState::decode(url).unwrap());
         assert_eq!(url, state.encode().unwrap());
@@ -346,43 +1428,889 @@ mod tests {
             program_arguments: "".into(),
             input: "[0.123] -> 0.123\n[0.123, 0.5] -> 0.5\n[0, 0, 1, 1, 0, 1] -> 1\n[0, 0, 1, 1, 0, 1, 0.5] -> 0.5\n[0.75, 0.75] -> 0.375\n[0.75, 0.75, 0.75] -> 0.5625\n[0.336, 0.467, 0.016, 0.469] -> 0.499350386816\n[0.469, 0.067, 0.675, 0.707] -> 0.4961100146\n[0.386, 0.224, 0.507, 0.099, 0.742] -> 0.499658027097344\n[0.796, 0.019, 0, 1, 0.217] -> 0.338830368\n[0.756, 0.924, 0.001, 0.046, 0.962, 0.001, 0.144] -> 0.6291619858201004\n".into(),
             input_encoding: "utf-8".into(),
+            extra_query: Vec::new(),
         };
         assert_eq!(state, LinkState::decode(url).unwrap());
         let ok_url = "https://ato.pxeger.com/run?1=ZZFNTsMwEIXFNqewuopREvz_U6k9QW8ALAJNhKUktpK0Uk_Cgk0lBKfgInAaHDulFKRInvne87yx8vLuDuOT7Y6v9erubTfWufpoyvZhWwKzLHie4pxcG1i43m5TeEOi5XNvWmf7EXS71h1AOYDOJbXtwQaYDlhXdSmCywSYzIIVaEuXVvuyyTbF4Bozpot8vYDQy17sXFH2fXlIjQeuN92Y1r7ObOYVMzw2dqhmAmFMP35dPd-iAhN6D_I1CFUSQeY7PlPume8zgMM3FUHB__nfa4XkE5InRuUFvdC4IEGkVEycCTkdCM-dnm1Ma8oRVUJhMdm9EnzRLuahSP7YBcYIYRbM_tokE8LCpihG6DBBMnKOEFwhIpGWlLGwsRZxG31-KMGnEEqVoogKFR8XrDpm-OhwsAgF-QUxY_MAQTQWWCuuCPLbsiT-oG8";
         assert_eq!(ok_url, state.encode().unwrap());
+
+        let (decoded, report) = LinkState::decode_with_report(url).unwrap();
+        assert_eq!(decoded, state);
+        assert_eq!(
+            report,
+            DecodeReport {
+                used_base64_fallback: true,
+                stripped_bytes: "¸".len(),
+                used_standard_alphabet: false,
+                undid_double_encoding: false,
+            },
+        );
+        let (_, report) = LinkState::decode_with_report(ok_url).unwrap();
+        assert_eq!(report, DecodeReport::default());
     }
 
     #[test]
-    fn roundtrip_all() {
-        let links = include_str!("../../tests/ato_links.txt");
-        let mut total_links = 0usize;
-        let mut compression_differs = 0usize;
-        for link in links.lines() {
-            let state = match LinkState::decode(link) {
-                Ok(state) => state,
-                Err(err) => panic!("decoding `{link}`: {err}"),
+    fn base64_mode() {
+        let url_safe = "https://ato.pxeger.com/run?1=m700KzUnp3LBgqWlJWm6FjfrHzXMedS47-GO7oc7ttu7WNgem3mq6cSSQ4sPbYo7Mu3hrh2emocWpkYf2npoA0jZzkVeWr5HFpycfGjJwx1NRxY-atxb5up6ctPDXQsPrXOv1jjacGjzo8bdh3YeWxsC1Pxo47pHDTMf7mwG2nJiqTGIvbuH6_CMxKN7Di1yqwRKLylOSi6GOmZ9tJIH0HH5CuH5RTkpSrFQYQA";
+        let standard = url_safe.replace('-', "%2B").replace('_', "/");
+        let mixed = url_safe.replace('_', "/");
+        // `+` is not escaped, so it would be a space in a form
+        let unescaped_plus = url_safe.replace('-', "+");
+        let state = LinkState::decode(url_safe).unwrap();
+
+        for url in [url_safe, &standard, &mixed, &unescaped_plus] {
+            assert_eq!(state, LinkState::decode(url).unwrap());
+        }
+        assert_eq!(
+            state,
+            LinkState::decode_with_base64_mode(url_safe, Base64Mode::UrlSafe).unwrap(),
+        );
+        assert_eq!(
+            state,
+            LinkState::decode_with_base64_mode(&standard, Base64Mode::Standard).unwrap(),
+        );
+        for url in [&standard, &mixed] {
+            assert!(matches!(
+                LinkState::decode_with_base64_mode(url, Base64Mode::UrlSafe),
+                Err(DecodeError::Base64(_)),
+            ));
+        }
+        for url in [url_safe, &mixed] {
+            assert!(matches!(
+                LinkState::decode_with_base64_mode(url, Base64Mode::Standard),
+                Err(DecodeError::Base64(_)),
+            ));
+        }
+    }
+
+    #[test]
+    fn set_schema() {
+        let mut state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::default()
+        };
+        state.set_schema(LinkSchema::V0).unwrap();
+        assert_eq!(state.schema, LinkSchema::V0);
+        state.set_schema(LinkSchema::V1).unwrap();
+        state.options = r#"["-O"]"#.into();
+        assert!(matches!(
+            state.set_schema(LinkSchema::V0),
+            Err(SchemaError::OptionsUnsupported),
+        ));
+        state.options.clear();
+        state.program_arguments = r#"["1"]"#.into();
+        assert!(matches!(
+            state.set_schema(LinkSchema::V0),
+            Err(SchemaError::ProgramArgumentsUnsupported),
+        ));
+        assert_eq!(state.schema, LinkSchema::V1);
+    }
+
+    #[test]
+    fn extra_query() {
+        let url = "https://ato.pxeger.com/run?L=python&preview=1&ui=a+b";
+        assert!(matches!(
+            LinkState::decode(url),
+            Err(DecodeError::UnknownKey(key)) if key == "preview",
+        ));
+        let state = LinkState::decode_permissive(url).unwrap();
+        assert_eq!(
+            state,
+            LinkState {
+                language: "python".into(),
+                extra_query: vec![("preview".into(), "1".into()), ("ui".into(), "a b".into()),],
+                ..LinkState::default()
+            },
+        );
+        assert_eq!(url, state.encode().unwrap());
+
+        let state = LinkState {
+            code: "print(1)".into(),
+            ..state
+        };
+        let url = state.encode().unwrap();
+        assert!(url.ends_with("&preview=1&ui=a+b"));
+        assert_eq!(state, LinkState::decode_permissive(&url).unwrap());
+        assert_eq!(state.size_report().unwrap().url, url.len());
+    }
+
+    #[test]
+    fn content_eq() {
+        let v1 = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::default()
+        };
+        let v0 = LinkState {
+            schema: LinkSchema::V0,
+            ..v1.clone()
+        };
+        assert_ne!(v0, v1);
+        assert!(v0.content_eq(&v1));
+        // The same data, compressed differently
+        let mut compressed = Vec::new();
+        DeflateEncoder::new(&*v1.serialize_mp().unwrap(), Compression::fast())
+            .read_to_end(&mut compressed)
+            .unwrap();
+        let url = format!("{RUN_URL}?1={}", URL_SAFE_NO_PAD.encode(compressed));
+        assert_ne!(url, v1.encode().unwrap());
+        assert!(LinkState::decode(&url).unwrap().content_eq(&v1));
+        let other = LinkState {
+            code_encoding: "utf-8".into(),
+            ..v1.clone()
+        };
+        assert!(!other.content_eq(&v1));
+    }
+
+    #[test]
+    fn encoded_mp() {
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::default()
+        };
+        let mp = state.encoded_mp().unwrap();
+        let (schema, raw) = LinkState::decode_raw(&state.encode().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!((schema, raw), (LinkSchema::V1, mp));
+        assert_eq!(
+            LinkState::decode_raw("https://ato.pxeger.com/run?L=python").unwrap(),
+            None,
+        );
+    }
+
+    #[test]
+    fn query_keys() {
+        for key in ATO_QUERY_KEYS {
+            LinkState::decode(&format!("{RUN_URL}?{key}=")).unwrap();
+        }
+        assert!(matches!(
+            LinkState::decode(&format!("{RUN_URL}?2=")),
+            Err(DecodeError::UnknownKey(key)) if key == "2",
+        ));
+    }
+
+    #[test]
+    fn repeated_language() {
+        let state = LinkState {
+            language: "python".into(),
+            ..LinkState::default()
+        };
+        let url = "https://ato.pxeger.com/run?L=python&l=python";
+        assert_eq!(state, LinkState::decode(url).unwrap());
+        let url = "https://ato.pxeger.com/run?L=python&L=python";
+        assert_eq!(state, LinkState::decode(url).unwrap());
+        let url = "https://ato.pxeger.com/run?L=python&l=zsh";
+        assert!(matches!(
+            LinkState::decode(url),
+            Err(DecodeError::MultipleLanguages),
+        ));
+    }
+
+    #[test]
+    fn multiple_versions() {
+        let v0 = LinkState {
+            schema: LinkSchema::V0,
+            language: "python".into(),
+            code: "print(0)".into(),
+            ..LinkState::default()
+        };
+        let v1 = LinkState {
+            schema: LinkSchema::V1,
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::default()
+        };
+        let v0_url = v0.encode().unwrap();
+        let v1_url = v1.encode().unwrap();
+        let v0_data = v0_url
+            .strip_prefix(RUN_URL)
+            .unwrap()
+            .strip_prefix('?')
+            .unwrap();
+        let v1_data = v1_url
+            .strip_prefix(RUN_URL)
+            .unwrap()
+            .strip_prefix('?')
+            .unwrap();
+
+        let url = format!("{RUN_URL}?{v0_data}&{v1_data}");
+        assert!(matches!(
+            LinkState::decode(&url),
+            Err(DecodeError::MultipleVersions(
+                LinkSchema::V0,
+                LinkSchema::V1
+            )),
+        ));
+        assert_eq!(v1, LinkState::decode_prefer_max(&url).unwrap());
+
+        let url = format!("{RUN_URL}?{v1_data}&{v0_data}");
+        assert!(matches!(
+            LinkState::decode(&url),
+            Err(DecodeError::MultipleVersions(
+                LinkSchema::V1,
+                LinkSchema::V0
+            )),
+        ));
+        assert_eq!(v1, LinkState::decode_prefer_max(&url).unwrap());
+
+        let url = format!("{RUN_URL}?{v0_data}&{v0_data}");
+        assert_eq!(v0, LinkState::decode_prefer_max(&url).unwrap());
+    }
+
+    #[test]
+    fn padded() {
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::default()
+        };
+        let url = state.encode().unwrap();
+        for padding in ["=", "==", "%3D%3D"] {
+            let padded = format!("{url}{padding}");
+            let (decoded, report) = LinkState::decode_with_report(&padded).unwrap();
+            assert_eq!(state, decoded);
+            // Padding is stripped before the first decode, so the slow path
+            // is not needed
+            assert!(!report.used_base64_fallback, "{padding}");
+            assert!(matches!(
+                LinkState::decode_with_base64_mode(&padded, Base64Mode::UrlSafe),
+                Err(DecodeError::Base64(_)),
+            ));
+        }
+    }
+
+    #[test]
+    fn wrapped_lines() {
+        let url = "https://ato.pxeger.com/run?1=m72soLIkIz9vwYIFKwqKMvNKNAw1F4ABAA";
+        // Wrapped and indented in a comment
+        let wrapped = "https://ato.pxeger.com/run?1=m72soLIkIz9vwYIF\n    KwqKMvNKNAw1F4ABAA\n";
+        let state = LinkState::decode(url).unwrap();
+        assert_eq!(state, LinkState::decode(wrapped).unwrap());
+        assert_eq!(
+            state,
+            LinkState::decode_with_base64_mode(wrapped, Base64Mode::UrlSafe).unwrap(),
+        );
+        assert!(matches!(
+            LinkState::decode_with(wrapped, &DecodeOptions::strict()),
+            Err(DecodeError::Base64(_)),
+        ));
+        let options = DecodeOptions {
+            join_wrapped_lines: true,
+            ..DecodeOptions::strict()
+        };
+        assert_eq!(state, LinkState::decode_with(wrapped, &options).unwrap());
+    }
+
+    #[test]
+    fn double_encoded() {
+        let url = "https://ato.pxeger.com/run?1=m700KzUnp3LBgqWlJWm6FjfrHzXMedS47-GO7oc7ttu7WNgem3mq6cSSQ4sPbYo7Mu3hrh2emocWpkYf2npoA0jZzkVeWr5HFpycfGjJwx1NRxY-atxb5up6ctPDXQsPrXOv1jjacGjzo8bdh3YeWxsC1Pxo47pHDTMf7mwG2nJiqTGIvbuH6_CMxKN7Di1yqwRKLylOSi6GOmZ9tJIH0HH5CuH5RTkpSrFQYQA";
+        // The link in the standard base64 alphabet, with `+` and `/` encoded
+        // twice
+        let double = url.replace('-', "%252B").replace('_', "%252F");
+        let options = DecodeOptions {
+            tolerate_junk_base64: false,
+            ..DecodeOptions::default()
+        };
+        assert!(matches!(
+            LinkState::decode_with(&double, &options),
+            Err(DecodeError::Base64(_)),
+        ));
+        let options = DecodeOptions {
+            tolerate_junk_base64: false,
+            undo_double_encoding: true,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(
+            LinkState::decode(url).unwrap(),
+            LinkState::decode_with(&double, &options).unwrap(),
+        );
+    }
+
+    #[test]
+    fn missing_scheme() {
+        let url = "https://ato.pxeger.com/run?1=m72soLIkIz9vwYIFKwqKMvNKNAw1F4ABAA";
+        let state = LinkState::decode(url).unwrap();
+        let bare = url.strip_prefix("https://").unwrap();
+        assert_eq!(state, LinkState::decode(bare).unwrap());
+        assert!(matches!(
+            LinkState::decode_strict(bare),
+            Err(DecodeError::Url(url::ParseError::RelativeUrlWithoutBase)),
+        ));
+        assert_eq!(state, LinkState::decode_strict(url).unwrap());
+        assert!(matches!(
+            LinkState::decode("example.com/run?L=python"),
+            Err(DecodeError::Url(url::ParseError::RelativeUrlWithoutBase)),
+        ));
+    }
+
+    #[test]
+    fn encode_url() {
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            extra_query: vec![("x".into(), "a b".into())],
+            ..LinkState::default()
+        };
+        let u = state.encode_url().unwrap();
+        assert_eq!(u.as_str(), state.encode().unwrap());
+        assert_eq!(u.domain(), Some("ato.pxeger.com"));
+        assert_eq!(
+            u.query(),
+            Some("1=m72soLIkIz9vwYIFKwqKMvNKNAw1F4ABAA&x=a+b")
+        );
+        let pairs: Vec<_> = u.query_pairs().map(|(k, _)| k.into_owned()).collect();
+        assert_eq!(pairs, ["1", "x"]);
+    }
+
+    #[test]
+    fn fragment() {
+        let url = "https://ato.pxeger.com/run?1=m72soLIkIz9vwYIFKwqKMvNKNAw1F4ABAA";
+        let state = LinkState::decode(url).unwrap();
+        for fragment in ["#footer", "#", "#1=AAAA&L=sed"] {
+            let with_fragment = format!("{url}{fragment}");
+            assert_eq!(state, LinkState::decode(&with_fragment).unwrap());
+            assert_eq!(url, state.encode().unwrap());
+            assert!(matches!(diagnose(&with_fragment), Diagnosis::Url));
+        }
+    }
+
+    #[test]
+    fn language_only() {
+        let url = "https://ato.pxeger.com/run?L=python";
+        let state = LinkState {
+            language: "python".into(),
+            ..LinkState::default()
+        };
+        assert_eq!(state, LinkState::decode(url).unwrap());
+        assert_eq!(url, state.encode().unwrap());
+        let mut s = String::from("[ATO](");
+        state.encode_into(&mut s).unwrap();
+        assert_eq!(s, format!("[ATO]({url}"));
+        assert_eq!(state.encode_language_only().as_deref(), Some(url));
+        assert_eq!(LinkState::default().encode_language_only(), None);
+
+        // Encodings are only empty when they are absent from the link
+        let state = LinkState {
+            language: "python".into(),
+            header_encoding: "utf-8".into(),
+            code_encoding: "utf-8".into(),
+            footer_encoding: "utf-8".into(),
+            input_encoding: "utf-8".into(),
+            ..LinkState::default()
+        };
+        let encoded = state.encode().unwrap();
+        assert!(encoded.starts_with("https://ato.pxeger.com/run?1="));
+        assert_eq!(state, LinkState::decode(&encoded).unwrap());
+        assert_eq!(state.encode_language_only(), None);
+        // The default encoding is written out, like ATO does
+        let mp = state.serialize_mp().unwrap();
+        assert_eq!(mp.windows(6).filter(|w| w == b"\xa5utf-8").count(), 4);
+    }
+
+    #[test]
+    fn escaped_language() {
+        let url = "https://ato.pxeger.com/run?L=c%2B%2B";
+        let state = LinkState {
+            language: "c++".into(),
+            ..LinkState::default()
+        };
+        assert_eq!(state, LinkState::decode(url).unwrap());
+        assert_eq!(url, state.encode().unwrap());
+        // An unescaped `+` is a space, like with URLSearchParams in ATO
+        assert_eq!(
+            LinkState::decode("https://ato.pxeger.com/run?L=c++")
+                .unwrap()
+                .language,
+            "c  ",
+        );
+
+        // With data, the language is in the data, so it is not escaped
+        let state = LinkState {
+            language: "c++".into(),
+            ..LinkState::ato_ui_default()
+        };
+        assert_eq!(state, LinkState::decode(&state.encode().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn ato_ui_default() {
+        let mut state = LinkState::ato_ui_default();
+        assert_eq!(state.schema, LinkSchema::V1);
+        assert_eq!(state.code_encoding, "utf-8");
+        assert_ne!(state, LinkState::default());
+        state.language = "python".into();
+        state.code = "print(1)".into();
+        let decoded = LinkState::decode(&state.encode().unwrap()).unwrap();
+        assert_eq!(state, decoded);
+    }
+
+    #[test]
+    fn normalize_newlines() {
+        let mut state = LinkState {
+            header: "a\r\nb\rc".into(),
+            code: "print(1)\r\n".into(),
+            footer: "DQo".into(),
+            footer_encoding: "base64".into(),
+            input: "1\n2".into(),
+            ..LinkState::ato_ui_default()
+        };
+        state.normalize_newlines();
+        assert_eq!(state.header, "a\nb\nc");
+        assert_eq!(state.code, "print(1)\n");
+        assert_eq!(state.footer, "DQo");
+        assert_eq!(state.input, "1\n2");
+    }
+
+    #[test]
+    fn strip_bom() {
+        let mut state = LinkState {
+            header: "\u{feff}import sys".into(),
+            code: "\u{feff}\u{feff}print(1)".into(),
+            footer: "77u_".into(),
+            footer_encoding: "base64".into(),
+            input: "a\u{200b}b\u{feff}".into(),
+            ..LinkState::ato_ui_default()
+        };
+        let mut bom = state.clone();
+        bom.strip_bom();
+        assert_eq!(bom.header, "import sys");
+        assert_eq!(bom.code, "\u{feff}print(1)");
+        assert_eq!(bom.footer, "77u_");
+        assert_eq!(bom.input, "a\u{200b}b\u{feff}");
+        let options = EncodeOptions {
+            strip_bom: true,
+            ..EncodeOptions::default()
+        };
+        assert_eq!(state.encode_with(&options).unwrap(), bom.encode().unwrap());
+        assert_eq!(
+            state.encode_with(&EncodeOptions::default()).unwrap(),
+            state.encode().unwrap(),
+        );
+        state.strip_zero_width();
+        assert_eq!(state.header, "import sys");
+        assert_eq!(state.code, "print(1)");
+        assert_eq!(state.footer, "77u_");
+        assert_eq!(state.input, "ab");
+    }
+
+    #[test]
+    fn preview() {
+        let state = LinkState {
+            code: "“⁾ḋḷ?D8=ƙʂ".into(),
+            footer: "print".into(),
+            ..LinkState::default()
+        };
+        assert_eq!(state.code_preview(0), "");
+        assert_eq!(state.code_preview(3), "“⁾ḋ");
+        assert_eq!(state.code_preview(100), state.code);
+        assert_eq!(state.footer_preview(5), "print");
+        assert_eq!(state.header_preview(5), "");
+    }
+
+    #[test]
+    fn size_report() {
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::default()
+        };
+        let report = state.size_report().unwrap();
+        assert_eq!(report.raw_mp, state.serialize_mp().unwrap().len());
+        assert_eq!(report.base64, (report.compressed * 4).div_ceil(3));
+        assert_eq!(report.url, state.encode().unwrap().len());
+        assert!(report.to_string().starts_with(&format!(
+            "MessagePack: {} bytes\ncompressed:  {} bytes (",
+            report.raw_mp, report.compressed,
+        )));
+
+        let state = LinkState {
+            language: "python".into(),
+            ..LinkState::default()
+        };
+        let report = state.size_report().unwrap();
+        assert_eq!(
+            report,
+            SizeReport {
+                url: "https://ato.pxeger.com/run?L=python".len(),
+                ..SizeReport::default()
+            },
+        );
+        assert_eq!(
+            report.to_string(),
+            "MessagePack: 0 bytes\ncompressed:  0 bytes\nbase64:      0 bytes\nURL:         35 bytes",
+        );
+    }
+
+    #[test]
+    fn data_only() {
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            code_encoding: "utf-8".into(),
+            ..LinkState::ato_ui_default()
+        };
+        let (schema, data) = state.encode_data().unwrap();
+        assert_eq!(schema, LinkSchema::V1);
+        assert_eq!(state.encode().unwrap(), format!("{RUN_URL}?1={data}"));
+        assert_eq!(LinkState::decode_data(schema, &data).unwrap(), state);
+
+        // The data is written even when only the language is set
+        let language_only = LinkState {
+            language: "python".into(),
+            ..LinkState::default()
+        };
+        let (schema, data) = language_only.encode_data().unwrap();
+        assert_eq!(
+            LinkState::decode_data(schema, &data).unwrap(),
+            language_only,
+        );
+    }
+
+    #[test]
+    fn empty_data() {
+        for (url, schema) in [
+            ("https://ato.pxeger.com/run?0=", LinkSchema::V0),
+            ("https://ato.pxeger.com/run?1=", LinkSchema::V1),
+        ] {
+            let state = LinkState {
+                schema,
+                ..LinkState::default()
             };
-            let encoded = match state.encode() {
-                Ok(encoded) => encoded,
-                Err(err) => panic!("encoding `{link}`: {err}"),
+            assert_eq!(state, LinkState::decode(url).unwrap());
+        }
+        let state = LinkState {
+            language: "python".into(),
+            ..LinkState::default()
+        };
+        assert_eq!(
+            state,
+            LinkState::decode("https://ato.pxeger.com/run?1=&L=python").unwrap(),
+        );
+    }
+
+    #[test]
+    fn schema_mismatch() {
+        let mp = rmp_serde::to_vec(&["python"; 9]).unwrap();
+        let compressed = compress(&mp, CompressionSettings::DEFAULT).unwrap();
+        let url = format!("{RUN_URL}?1={}", URL_SAFE_NO_PAD.encode(compressed));
+        assert!(matches!(
+            LinkState::decode(&url),
+            Err(DecodeError::SchemaMismatch {
+                schema: LinkSchema::V1,
+                expected: 11,
+                found: 9,
+            }),
+        ));
+    }
+
+    #[test]
+    fn compat_layouts() {
+        let state = LinkState {
+            schema: LinkSchema::V0,
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::ato_ui_default()
+        };
+        let url = state.encode().unwrap().replace("?0=", "?1=");
+        assert!(matches!(
+            LinkState::decode(&url),
+            Err(DecodeError::SchemaMismatch { .. }),
+        ));
+        let options = DecodeOptions {
+            compat_layouts: true,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(state, LinkState::decode_with(&url, &options).unwrap());
+    }
+
+    #[test]
+    fn tolerate_field_count() {
+        let url = |fields: &[&str]| {
+            let mp = rmp_serde::to_vec(fields).unwrap();
+            let compressed = compress(&mp, CompressionSettings::DEFAULT).unwrap();
+            format!("{RUN_URL}?1={}", URL_SAFE_NO_PAD.encode(compressed))
+        };
+        let options = DecodeOptions {
+            tolerate_field_count: true,
+            ..DecodeOptions::default()
+        };
+        let mut fields = [
+            "python", "", "", "utf-8", "print(1)", "utf-8", "", "utf-8", "", "", "utf-8",
+        ]
+        .to_vec();
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::ato_ui_default()
+        };
+        assert_eq!(state, LinkState::decode(&url(&fields)).unwrap());
+
+        // A 12th field, like a debug flag, would be ignored
+        fields.push("debug");
+        assert!(matches!(
+            LinkState::decode(&url(&fields)),
+            Err(DecodeError::SchemaMismatch {
+                expected: 11,
+                found: 12,
+                ..
+            }),
+        ));
+        assert_eq!(
+            state,
+            LinkState::decode_with(&url(&fields), &options).unwrap()
+        );
+
+        // Missing trailing fields are empty
+        fields.truncate(6);
+        let truncated = LinkState {
+            footer_encoding: "".into(),
+            input_encoding: "".into(),
+            ..state
+        };
+        assert_eq!(
+            truncated,
+            LinkState::decode_with(&url(&fields), &options).unwrap(),
+        );
+    }
+
+    #[test]
+    fn diagnosis() {
+        let url = "https://ato.pxeger.com/run?1=m72soLIkIz9vwYIFKwqKMvNKNAw1F4ABAA";
+        let data = url.split_once("?1=").unwrap().1;
+        let mp = LinkState::decode(url).unwrap().serialize_mp().unwrap();
+        let fast = CompressionSettings {
+            level: 1,
+            ..CompressionSettings::DEFAULT
+        };
+        let recompressed = URL_SAFE_NO_PAD.encode(compress(&mp, fast).unwrap());
+        assert_ne!(recompressed, data);
+        // `python` with a str8 header, instead of a fixstr
+        let wide = [&b"\x9b\xd9\x06python"[..], &mp[8..]].concat();
+        let wide = URL_SAFE_NO_PAD.encode(compress(&wide, CompressionSettings::DEFAULT).unwrap());
+
+        assert!(matches!(diagnose(url), Diagnosis::Exact));
+        assert!(matches!(
+            diagnose(&format!("{RUN_URL}?x=1&1={data}")),
+            Diagnosis::QueryOrder,
+        ));
+        assert!(matches!(
+            diagnose(&format!("{url}==")),
+            Diagnosis::Base64Alphabet,
+        ));
+        assert!(matches!(
+            diagnose(&format!("{RUN_URL}?1={recompressed}")),
+            Diagnosis::Compression,
+        ));
+        assert!(matches!(
+            diagnose(&format!("{RUN_URL}?1={wide}")),
+            Diagnosis::Serialization,
+        ));
+        assert!(matches!(
+            diagnose(&url.replace("https", "http")),
+            Diagnosis::Url,
+        ));
+        assert!(matches!(
+            diagnose(&format!("{url}&L=python")),
+            Diagnosis::Url,
+        ));
+        assert!(matches!(diagnose("not a link"), Diagnosis::DecodeFailed(_),));
+    }
+
+    #[test]
+    fn golden() {
+        // Services key on the exact bytes of links, so a change to this URL
+        // means the DEFLATE output changed.
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::default()
+        };
+        assert_eq!(
+            state.encode().unwrap(),
+            "https://ato.pxeger.com/run?1=m72soLIkIz9vwYIFKwqKMvNKNAw1F4ABAA",
+        );
+        let data = "print(1)\n".repeat(10_000);
+        let mut expected = Vec::new();
+        DeflateEncoder::new(data.as_bytes(), Compression::best())
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(
+            compress(data.as_bytes(), CompressionSettings::DEFAULT).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn encode_best_effort() {
+        let links = include_str!("../../tests/ato_links.txt");
+        let mut shortened = 0;
+        for link in links.lines().take(200) {
+            let Ok(state) = LinkState::decode(link) else {
+                continue;
             };
-            if encoded != link {
-                let (data, language) = LinkState::decode_url(link).unwrap();
-                if let Some((schema, decoded_raw)) = data {
-                    compression_differs += 1;
-                    let encoded_raw = state.serialize_mp().unwrap();
-                    assert_eq!(state.schema, schema);
-                    assert_eq!(decoded_raw, encoded_raw);
-                }
-                if let Some(l) = language {
-                    assert_eq!(state.language, l);
-                }
+            let url = state.encode().unwrap();
+            let one = state.encode_best_effort(1).unwrap();
+            assert_eq!(one.url, url);
+            assert_eq!(one.settings, CompressionSettings::DEFAULT);
+            let best = state.encode_best_effort(usize::MAX).unwrap();
+            assert!(best.url.len() <= url.len());
+            assert_eq!(LinkState::decode(&best.url).unwrap(), state);
+            if best.url.len() < url.len() {
+                assert_ne!(best.settings, CompressionSettings::DEFAULT);
+                let mp = state.serialize_mp().unwrap();
+                let compressed = compress(&mp, best.settings).unwrap();
+                assert!(best.url.ends_with(&URL_SAFE_NO_PAD.encode(compressed)));
+                shortened += 1;
             }
-            state.parse().expect("can parse state");
-            total_links += 1;
         }
-        if compression_differs != 0 {
-            eprintln!("Compression differs for {compression_differs}/{total_links} links");
+        assert!(shortened > 0);
+    }
+
+    #[test]
+    fn on_decompress() {
+        use std::sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        };
+
+        let state = LinkState {
+            language: "python".into(),
+            footer: "#".repeat(100_000),
+            ..LinkState::default()
+        };
+        let url = state.encode().unwrap();
+        let progress = Arc::new(AtomicU64::new(0));
+        let options = DecodeOptions {
+            on_decompress: Some(ProgressCallback::new({
+                let progress = progress.clone();
+                move |n| {
+                    progress.store(n, Ordering::Relaxed);
+                    true
+                }
+            })),
+            ..DecodeOptions::default()
+        };
+        assert_eq!(state, LinkState::decode_with(&url, &options).unwrap());
+        let mp_len = state.serialize_mp().unwrap().len() as u64;
+        assert_eq!(progress.load(Ordering::Relaxed), mp_len);
+        assert_eq!(options, options.clone());
+        assert_ne!(options, DecodeOptions::default());
+
+        let limit = 10_000;
+        let options = DecodeOptions {
+            on_decompress: Some(ProgressCallback::new(move |n| n < limit)),
+            ..DecodeOptions::default()
+        };
+        assert!(matches!(
+            LinkState::decode_with(&url, &options),
+            Err(DecodeError::Cancelled(n)) if n >= limit && n < mp_len,
+        ));
+    }
+
+    #[test]
+    fn deflate_bomb() {
+        let max = DecodeOptions::default().max_decompressed_size;
+        let zeros = vec![0; max as usize + 1];
+        let mut compressed = Vec::new();
+        DeflateEncoder::new(&*zeros, Compression::best())
+            .read_to_end(&mut compressed)
+            .unwrap();
+        let url = format!("{RUN_URL}?1={}", URL_SAFE_NO_PAD.encode(&compressed));
+        assert!(matches!(
+            LinkState::decode(&url),
+            Err(DecodeError::TooLarge(n)) if n == max,
+        ));
+    }
+
+    #[test]
+    fn decode_options() {
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::ato_ui_default()
+        };
+        let url = state.encode().unwrap();
+        let junk = format!("{url}%C2%B8");
+        let repeated = format!("{url}&1={}", &url[url.find("?1=").unwrap() + 3..]);
+        let extra = format!("{url}&x=1");
+
+        let strict = DecodeOptions::strict();
+        assert_eq!(state, LinkState::decode_with(&url, &strict).unwrap());
+        assert!(LinkState::decode_with(&junk, &strict).is_err());
+        assert!(LinkState::decode_with(&repeated, &strict).is_err());
+        assert!(LinkState::decode_with(&extra, &strict).is_err());
+
+        let lenient = DecodeOptions::lenient();
+        assert_eq!(state, LinkState::decode_with(&junk, &lenient).unwrap());
+        assert_eq!(state, LinkState::decode_with(&repeated, &lenient).unwrap());
+        assert_eq!(
+            LinkState::decode_with(&extra, &lenient)
+                .unwrap()
+                .extra_query,
+            [("x".into(), "1".into())],
+        );
+
+        let small = DecodeOptions {
+            max_decompressed_size: 4,
+            ..DecodeOptions::default()
+        };
+        assert!(matches!(
+            LinkState::decode_with(&url, &small),
+            Err(DecodeError::TooLarge(4)),
+        ));
+    }
+
+    #[test]
+    fn zlib_header() {
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::default()
+        };
+        let mp = state.serialize_mp().unwrap();
+        let mut compressed = Vec::new();
+        ZlibEncoder::new(&*mp, Compression::best())
+            .read_to_end(&mut compressed)
+            .unwrap();
+        assert!(is_zlib_header(&compressed));
+        let url = format!("{RUN_URL}?1={}", URL_SAFE_NO_PAD.encode(&compressed));
+        assert!(matches!(
+            LinkState::decode(&url),
+            Err(DecodeError::UnexpectedZlibHeader),
+        ));
+    }
+
+    #[test]
+    fn gzip() {
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            ..LinkState::default()
+        };
+        let mp = state.serialize_mp().unwrap();
+        let mut compressed = Vec::new();
+        GzEncoder::new(&*mp, Compression::best())
+            .read_to_end(&mut compressed)
+            .unwrap();
+        let url = format!("{RUN_URL}?1={}", URL_SAFE_NO_PAD.encode(&compressed));
+        assert_eq!(state, LinkState::decode(&url).unwrap());
+    }
+
+    #[test]
+    fn roundtrip_all() {
+        let links = include_str!("../../tests/ato_links.txt");
+        let report = check_roundtrip(links);
+        assert!(report.is_ok(), "{report}");
+        // Links compressed or serialized differently than ATO does now, which
+        // still decode to the same data
+        assert_eq!(report.encoding_differs, 195, "{report}");
+        #[cfg(feature = "net")]
+        for link in links.lines() {
+            let state = LinkState::decode(link).unwrap();
+            state.parse().expect("can parse state");
         }
     }
 }
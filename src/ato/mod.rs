@@ -3,11 +3,26 @@
 //! Supports schema versions 0 and 1, and is based on the implementation as of
 //! commit [b1e7ff3](https://github.com/attempt-this-online/attempt-this-online/blob/b1e7ff39c15afc8194d958b8c9bbc5c3ebcd5730/frontend/lib/urls.ts)
 //! (2023-06-30).
+//!
+//! The MessagePack layouts of the versions differ only in that version 1 adds
+//! options after the language and program arguments before the input. Data
+//! with the layout of the other version than its key is an error, unless
+//! decoding with [`DecodeOptions::compat_layouts`](crate::DecodeOptions), in
+//! which case it is decoded with that layout and the schema is that of the
+//! layout. No link in `tests/ato_links.txt` needs this.
 
 mod api;
+#[cfg(feature = "cache")]
+mod cache;
+mod diff;
 mod link;
+mod sbcs;
 mod state;
 
 pub use api::*;
+#[cfg(feature = "cache")]
+pub use cache::*;
+pub use diff::*;
 pub use link::*;
+pub use sbcs::*;
 pub use state::*;
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+
+/// The code page for fields with the `sbcs` encoding, which is Jelly's code
+/// page. Byte 0x7F is `¶`, which is also written as a line feed.
+///
+/// See https://github.com/DennisMitchell/jellylanguage/wiki/Code-page
+pub const SBCS_CODE_PAGE: &str = concat!(
+    "¡¢£¤¥¦©¬®µ½¿€ÆÇÐÑ×ØŒÞßæçðıȷñ÷øœþ",
+    " !\"#$%&'()*+,-./0123456789:;<=>?",
+    "@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_",
+    "`abcdefghijklmnopqrstuvwxyz{|}~¶",
+    "°¹²³⁴⁵⁶⁷⁸⁹⁺⁻⁼⁽⁾ƁƇƊƑƓƘⱮƝƤƬƲȤɓƈɗƒɠ",
+    "ɦƙɱɲƥʠɼʂƭʋȥẠḄḌẸḤỊḲḶṂṆỌṚṢṬỤṾẈỴẒȦḂ",
+    "ĊḊĖḞĠḢİĿṀṄȮṖṘṠṪẆẊẎŻạḅḍẹḥịḳḷṃṇọṛṣ",
+    "ṭ§Äẉỵẓȧḃċḋėḟġḣŀṁṅȯṗṙṡṫẇẋẏż«»‘’“”",
+);
+
+lazy_static! {
+    static ref CHARS: Vec<char> = SBCS_CODE_PAGE.chars().collect();
+    static ref BYTES: HashMap<char, u8> = {
+        let mut bytes: HashMap<char, u8> =
+            CHARS.iter().zip(0..=255).map(|(&c, b)| (c, b)).collect();
+        bytes.insert('\n', 0x7f);
+        bytes
+    };
+}
+
+/// Encode text with the SBCS code page. On failure, returns the first char
+/// not in the code page.
+pub fn encode_sbcs(text: &str) -> Result<Vec<u8>, char> {
+    text.chars()
+        .map(|c| BYTES.get(&c).copied().ok_or(c))
+        .collect()
+}
+
+/// Decode bytes with the SBCS code page. Byte 0x7F decodes as a line feed.
+pub fn decode_sbcs(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| if b == 0x7f { '\n' } else { CHARS[b as usize] })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_page() {
+        assert_eq!(CHARS.len(), 256);
+        assert_eq!(BYTES.len(), 257);
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(encode_sbcs(&decode_sbcs(&bytes)), Ok(bytes));
+        assert_eq!(encode_sbcs("¶\n"), Ok(vec![0x7f, 0x7f]));
+        assert_eq!(encode_sbcs("a😀"), Err('😀'));
+    }
+}
@@ -1,20 +1,42 @@
-use std::io::Cursor;
+use std::{fmt, str};
 
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use serde_json::value::RawValue;
 use thiserror::Error;
 
-use crate::ato::{get_language, Language, LinkState};
+#[cfg(feature = "net")]
+use crate::ato::{get_language, get_languages_by_se_class};
+use crate::{
+    ato::{decode_sbcs, encode_sbcs, EncodeError, Language, LinkSchema, LinkState},
+    tio, Args,
+};
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct State {
     pub language: Option<&'static Language>,
     pub options: Vec<String>,
+    /// The indices of the options which were JSON scalars other than strings,
+    /// like `1.0` or `true`. They are kept as their literal text and encoded
+    /// unquoted again.
+    pub scalar_options: Vec<usize>,
     pub header: String,
     pub header_encoding: Encoding,
     pub code: String,
     pub code_encoding: Encoding,
     pub footer: String,
     pub footer_encoding: Encoding,
-    pub program_arguments: Vec<String>,
+    pub program_arguments: Args,
+    /// The indices of the program arguments which were JSON scalars other than
+    /// strings, like [`State::scalar_options`].
+    pub scalar_program_arguments: Vec<usize>,
     pub input: String,
     pub input_encoding: Encoding,
 }
@@ -36,53 +58,929 @@ pub enum ParseError {
     InvalidEncoding(String),
     #[error("invalid JSON for arguments: {0}")]
     InvalidJson(#[from] serde_json::Error),
+    #[error("arguments are not a JSON array: {0}")]
+    OptionsNotArray(serde_json::Value),
     #[error("object argument: {0}")]
     ObjectArg(serde_json::Value),
+    #[error("invalid base64: {0}")]
+    InvalidBase64(base64::DecodeError),
+    #[error("`{0}` is not in the SBCS code page")]
+    UnmappableSbcs(char),
 }
 
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    #[error("invalid base64 in code: {0}")]
+    InvalidBase64Code(base64::DecodeError),
+    #[error("code contains `{0}`, which is not in the SBCS code page")]
+    UnmappableSbcsCode(char),
+    #[error("invalid base64 in header: {0}")]
+    InvalidBase64Header(base64::DecodeError),
+    #[error("header contains `{0}`, which is not in the SBCS code page")]
+    UnmappableSbcsHeader(char),
+}
+
+/// A reason that a link cannot run, with the field it is in.
+#[derive(Debug, Error)]
+#[error("{field}: {error}")]
+pub struct Problem {
+    pub field: &'static str,
+    pub error: ParseError,
+}
+
+#[derive(Debug, Error)]
+pub enum TioConversionError {
+    #[error("no language")]
+    NoLanguage,
+    #[error("language `{0}` has no equivalent on TIO")]
+    UnmappedLanguage(String),
+    #[error("TIO links cannot have {0}")]
+    UnsupportedField(&'static str),
+    #[error("invalid base64 in {0}: {1}")]
+    InvalidBase64(&'static str, base64::DecodeError),
+}
+
+#[derive(Debug, Error)]
+pub enum RunBodyError {
+    #[error("MessagePack deserialize: {0}")]
+    MessagePack(#[from] rmp_serde::decode::Error),
+    #[error("invalid language `{0}`")]
+    InvalidLanguage(String),
+}
+
+/// A state encoded as a link for each site, for answers that link to both.
+/// A link is an error when the state cannot be encoded for that site.
+#[derive(Debug)]
+pub struct ShareLinks {
+    pub ato: Result<String, EncodeError>,
+    pub tio: Result<String, TioConversionError>,
+}
+
+impl From<State> for LinkState {
+    /// Convert to the fields of a link with schema version 1.
+    fn from(state: State) -> Self {
+        LinkState {
+            schema: LinkSchema::V1,
+            language: state.language.map(|l| l.id().into()).unwrap_or_default(),
+            options: encode_arg_list_with_scalars(&state.options, &state.scalar_options),
+            header: state.header,
+            header_encoding: state.header_encoding.as_str().into(),
+            code: state.code,
+            code_encoding: state.code_encoding.as_str().into(),
+            footer: state.footer,
+            footer_encoding: state.footer_encoding.as_str().into(),
+            program_arguments: encode_arg_list_with_scalars(
+                &state.program_arguments,
+                &state.scalar_program_arguments,
+            ),
+            input: state.input,
+            input_encoding: state.input_encoding.as_str().into(),
+            extra_query: Vec::new(),
+        }
+    }
+}
+
+impl From<&tio::LinkState> for LinkState {
+    /// Convert a TIO link to the fields of an ATO link with schema version 1.
+    /// The language id is kept as is, since the ids of the two sites mostly
+    /// agree, and the debug flag is dropped, since ATO has none. The code and
+    /// input are `utf-8`, unless they are not valid UTF-8, in which case they
+    /// are `base64`.
+    fn from(state: &tio::LinkState) -> Self {
+        let mut code = String::new();
+        let mut code_encoding = Encoding::Utf8;
+        set_field_bytes(&mut code, &mut code_encoding, state.code.clone());
+        let mut input = String::new();
+        let mut input_encoding = Encoding::Utf8;
+        set_field_bytes(&mut input, &mut input_encoding, state.input.clone());
+        LinkState {
+            schema: LinkSchema::V1,
+            language: state.language.clone(),
+            code,
+            code_encoding: code_encoding.as_str().into(),
+            // Each argument is a JSON string, so arguments with spaces or
+            // quotes are kept intact, unlike a space-joined list.
+            program_arguments: encode_arg_list(&state.args),
+            input,
+            input_encoding: input_encoding.as_str().into(),
+            ..LinkState::ato_ui_default()
+        }
+    }
+}
+
+/// An error decoding a field from its encoding.
+enum FieldError {
+    Base64(base64::DecodeError),
+    Sbcs(char),
+}
+
+/// The standard base64 alphabet, with optional padding.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
 impl LinkState {
+    /// Construct a link from its fields, each with its encoding, checking that
+    /// the text is valid for its encoding: base64 fields must be valid
+    /// standard base64 and SBCS fields must only have characters in the code
+    /// page. Options and program arguments can be set afterwards.
+    pub fn from_parts(
+        language: &str,
+        header: (&str, Encoding),
+        code: (&str, Encoding),
+        footer: (&str, Encoding),
+        input: (&str, Encoding),
+    ) -> Result<LinkState, ParseError> {
+        for (text, encoding) in [header, code, footer, input] {
+            match field_bytes(text, encoding) {
+                Ok(_) => {}
+                Err(FieldError::Base64(err)) => return Err(ParseError::InvalidBase64(err)),
+                Err(FieldError::Sbcs(c)) => return Err(ParseError::UnmappableSbcs(c)),
+            }
+        }
+        Ok(LinkState {
+            schema: LinkSchema::V1,
+            language: language.into(),
+            options: String::new(),
+            header: header.0.into(),
+            header_encoding: header.1.as_str().into(),
+            code: code.0.into(),
+            code_encoding: code.1.as_str().into(),
+            footer: footer.0.into(),
+            footer_encoding: footer.1.as_str().into(),
+            program_arguments: String::new(),
+            input: input.0.into(),
+            input_encoding: input.1.as_str().into(),
+            extra_query: Vec::new(),
+        })
+    }
+
+    /// Parse the fields, looking up the language in ATO's `languages.json`.
+    #[cfg(feature = "net")]
     pub fn parse(self) -> Result<State, ParseError> {
+        self.parse_with(get_language)
+    }
+
+    /// Parse the fields, looking up the language with `lookup`, so languages
+    /// can come from a local copy of `languages.json`, such as without the
+    /// `net` feature.
+    pub fn parse_with<F>(self, lookup: F) -> Result<State, ParseError>
+    where
+        F: FnOnce(&str) -> Option<&'static Language>,
+    {
         let language = if !self.language.is_empty() {
-            Some(
-                get_language(&self.language)
-                    .ok_or_else(|| ParseError::InvalidLanguage(self.language))?,
-            )
+            Some(lookup(&self.language).ok_or(ParseError::InvalidLanguage(self.language))?)
         } else {
             None
         };
+        let (options, scalar_options) = parse_arg_list(self.options)?;
+        let (program_arguments, scalar_program_arguments) = parse_arg_list(self.program_arguments)?;
         Ok(State {
             language,
-            options: parse_arg_list(self.options)?,
+            options,
+            scalar_options,
             header: self.header,
             header_encoding: self.header_encoding.try_into()?,
             code: self.code,
             code_encoding: self.code_encoding.try_into()?,
             footer: self.footer,
             footer_encoding: self.footer_encoding.try_into()?,
-            program_arguments: parse_arg_list(self.program_arguments)?,
+            program_arguments: Args(program_arguments),
+            scalar_program_arguments,
             input: self.input,
             input_encoding: self.input_encoding.try_into()?,
         })
     }
+
+    /// Parse the fields without looking up the language, so it works without
+    /// `languages.json`, such as without the `net` feature. The language of
+    /// the state is always `None`, so keep the id from the link if needed.
+    pub fn parse_unchecked(self) -> Result<State, ParseError> {
+        LinkState {
+            language: String::new(),
+            ..self
+        }
+        .parse_with(|_| None)
+    }
+
+    /// Check that the link can run, looking up the language in ATO's
+    /// `languages.json`. See [`LinkState::is_runnable_with`].
+    #[cfg(feature = "net")]
+    pub fn is_runnable(&self) -> Result<(), Vec<Problem>> {
+        self.is_runnable_with(get_language)
+    }
+
+    /// Check that the link can run, looking up the language with `lookup`,
+    /// and return every problem, instead of stopping at the first like
+    /// [`LinkState::parse_with`]: the language must exist, the encodings must
+    /// be known and the fields valid in them, and the options and program
+    /// arguments must be JSON arrays.
+    pub fn is_runnable_with<F>(&self, lookup: F) -> Result<(), Vec<Problem>>
+    where
+        F: FnOnce(&str) -> Option<&'static Language>,
+    {
+        let mut problems = Vec::new();
+        let mut problem = |field, error| problems.push(Problem { field, error });
+        if self.language.is_empty() || lookup(&self.language).is_none() {
+            problem(
+                "language",
+                ParseError::InvalidLanguage(self.language.clone()),
+            );
+        }
+        for (field, args) in [
+            ("options", &self.options),
+            ("program_arguments", &self.program_arguments),
+        ] {
+            if let Err(err) = parse_arg_list(args.clone()) {
+                problem(field, err);
+            }
+        }
+        for (field, text, encoding) in [
+            ("header", &self.header, &self.header_encoding),
+            ("code", &self.code, &self.code_encoding),
+            ("footer", &self.footer, &self.footer_encoding),
+            ("input", &self.input, &self.input_encoding),
+        ] {
+            match Encoding::try_from(encoding.clone()) {
+                Ok(encoding) => match field_bytes(text, encoding) {
+                    Ok(_) => {}
+                    Err(FieldError::Base64(err)) => problem(field, ParseError::InvalidBase64(err)),
+                    Err(FieldError::Sbcs(c)) => problem(field, ParseError::UnmappableSbcs(c)),
+                },
+                Err(err) => problem(field, err),
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// The code as the bytes of its text. Code in `utf-8` and `sbcs` is its
+    /// text as UTF-8 and `base64` code is decoded. Returns `None` for an
+    /// unknown encoding or invalid base64.
+    pub(crate) fn code_text_bytes(&self) -> Option<Vec<u8>> {
+        match Encoding::try_from(self.code_encoding.clone()).ok()? {
+            Encoding::Utf8 | Encoding::Sbcs => Some(self.code.clone().into_bytes()),
+            Encoding::Base64 => decode_base64(&self.code).ok(),
+        }
+    }
+
+    /// Reports whether two links run the same program, even when they are
+    /// encoded differently, by comparing their parsed fields. The schema
+    /// version, the extra query keys, empty and `utf-8` encodings, and the
+    /// formatting of the argument lists are ignored. The languages are compared
+    /// by id, so they do not need to be looked up. Links that cannot be parsed
+    /// are not equal.
+    pub fn semantically_eq(&self, other: &LinkState) -> bool {
+        fn parse_fields(state: &LinkState) -> Option<State> {
+            state.clone().parse_unchecked().ok()
+        }
+        self.language == other.language
+            && match (parse_fields(self), parse_fields(other)) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            }
+    }
+
+    /// Build a link from a code block in a Stack Exchange answer, with the
+    /// class it is highlighted with, e.g., `lang-py`, for turning any code
+    /// block into a runnable link. When several languages have the class, the
+    /// first by name is used. Returns `None` when no language has it.
+    #[cfg(feature = "net")]
+    pub fn from_se_block(class: &str, code: &str) -> Option<LinkState> {
+        LinkState::from_se_block_with(class, code, |class| {
+            get_languages_by_se_class(class).first().copied()
+        })
+    }
+
+    /// Build a link from a code block in a Stack Exchange answer, looking up
+    /// the language for the class with `lookup`. See
+    /// [`LinkState::from_se_block`].
+    pub fn from_se_block_with<F>(class: &str, code: &str, lookup: F) -> Option<LinkState>
+    where
+        F: FnOnce(&str) -> Option<&'static Language>,
+    {
+        let language = lookup(class)?;
+        // Code in a single-byte character set is shown as its characters
+        let code_encoding = if language.is_sbcs() && encode_sbcs(code).is_ok() {
+            Encoding::Sbcs
+        } else {
+            Encoding::Utf8
+        };
+        Some(LinkState::from(State {
+            language: Some(language),
+            code: code.into(),
+            code_encoding,
+            ..State::default()
+        }))
+    }
+
+    /// The same link with a different language, for running the same code in
+    /// another language. Every other field is kept, so the encodings are not
+    /// changed, even when the new language has a different character set. The
+    /// language is not checked to exist; use
+    /// [`LinkState::with_language_checked`] for that.
+    pub fn with_language(self, language: &str) -> LinkState {
+        LinkState {
+            language: language.into(),
+            ..self
+        }
+    }
+
+    /// The same link with a different language, checking that the language
+    /// is in ATO's `languages.json`. See [`LinkState::with_language`].
+    #[cfg(feature = "net")]
+    pub fn with_language_checked(self, language: &str) -> Result<LinkState, ParseError> {
+        self.with_language_checked_with(language, get_language)
+    }
+
+    /// The same link with a different language, checking that the language
+    /// exists with `lookup`. See [`LinkState::with_language`].
+    pub fn with_language_checked_with<F>(
+        self,
+        language: &str,
+        lookup: F,
+    ) -> Result<LinkState, ParseError>
+    where
+        F: FnOnce(&str) -> Option<&'static Language>,
+    {
+        let language =
+            lookup(language).ok_or_else(|| ParseError::InvalidLanguage(language.into()))?;
+        Ok(self.with_language(language.id()))
+    }
+
+    /// Set the options, encoded as a JSON array of strings.
+    pub fn set_options(&mut self, options: &[&str]) {
+        self.options = encode_arg_list(options);
+    }
+
+    /// Set the program arguments, encoded as a JSON array of strings.
+    pub fn set_program_arguments(&mut self, args: &[&str]) {
+        self.program_arguments = encode_arg_list(args);
+    }
+
+    /// Re-serialize the options and program arguments like ATO's
+    /// `JSON.stringify` would, so argument lists that were written by hand,
+    /// e.g., `["1", "2"]` with spaces, are encoded as ATO encodes them.
+    pub fn canonicalize_arg_lists(&mut self) -> Result<(), ParseError> {
+        let (options, scalar_options) = parse_arg_list(self.options.clone())?;
+        let (program_arguments, scalar_program_arguments) =
+            parse_arg_list(self.program_arguments.clone())?;
+        self.options = encode_arg_list_with_scalars(&options, &scalar_options);
+        self.program_arguments =
+            encode_arg_list_with_scalars(&program_arguments, &scalar_program_arguments);
+        Ok(())
+    }
+
+    /// Strip the link down to its code as text, for quoting it without the
+    /// link. The header, code, and footer are joined in that order with a line
+    /// feed between each, with an empty header or footer omitted along with
+    /// its line feed. Base64 fields are decoded as UTF-8, replacing invalid
+    /// sequences, and SBCS fields are already text.
+    pub fn into_code(self) -> Result<String, ParseError> {
+        let header = field_text(self.header, self.header_encoding)?;
+        let code = field_text(self.code, self.code_encoding)?;
+        let footer = field_text(self.footer, self.footer_encoding)?;
+        let mut text = String::with_capacity(header.len() + code.len() + footer.len() + 2);
+        if !header.is_empty() {
+            text.push_str(&header);
+            text.push('\n');
+        }
+        text.push_str(&code);
+        if !footer.is_empty() {
+            text.push('\n');
+            text.push_str(&footer);
+        }
+        Ok(text)
+    }
+}
+
+impl State {
+    /// Decode the MessagePack body of a request to ATO's `/run` endpoint, as
+    /// in the `curl` examples of its API docs, looking up the language in
+    /// ATO's `languages.json`. See [`State::from_run_body_with`].
+    #[cfg(feature = "net")]
+    pub fn from_run_body(bytes: &[u8]) -> Result<State, RunBodyError> {
+        State::from_run_body_with(bytes, get_language)
+    }
+
+    /// Decode the MessagePack body of a request to ATO's `/run` endpoint,
+    /// looking up the language with `lookup`. Unlike links, the body is a map
+    /// with the keys `language`, `code`, `input`, `options`, and `arguments`,
+    /// where the others, like `timeout`, are ignored. The API has no header or
+    /// footer, so the code is the whole program, and the encodings of the
+    /// code and input are chosen like the ATO frontend does.
+    pub fn from_run_body_with<F>(bytes: &[u8], lookup: F) -> Result<State, RunBodyError>
+    where
+        F: FnOnce(&str) -> Option<&'static Language>,
+    {
+        let body: RunBody = rmp_serde::from_slice(bytes)?;
+        let language = if !body.language.is_empty() {
+            Some(lookup(&body.language).ok_or(RunBodyError::InvalidLanguage(body.language))?)
+        } else {
+            None
+        };
+        let mut state = State {
+            language,
+            options: body.options,
+            program_arguments: Args(body.arguments),
+            ..State::default()
+        };
+        state.code_encoding = choose_encoding(&body.code.0, language);
+        set_field_bytes(&mut state.code, &mut state.code_encoding, body.code.0);
+        state.input_encoding = choose_encoding(&body.input.0, language);
+        set_field_bytes(&mut state.input, &mut state.input_encoding, body.input.0);
+        Ok(state)
+    }
+
+    /// Check that the fields are well-formed for their encodings.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.code_bytes().map(|_| ())
+    }
+
+    /// Reports whether the code is valid UTF-8, once decoded from its
+    /// encoding.
+    pub fn code_is_valid_utf8(&self) -> bool {
+        match self.code_encoding {
+            Encoding::Utf8 => true,
+            _ => self
+                .code_bytes()
+                .is_ok_and(|code| str::from_utf8(&code).is_ok()),
+        }
+    }
+
+    /// The total size in bytes of the header, code, footer, input, and program
+    /// arguments, once decoded from their encodings. This is what the backend
+    /// processes, rather than the length of the link. Each SBCS character
+    /// counts as one byte and base64 fields count their decoded length, even
+    /// when malformed.
+    pub fn total_bytes(&self) -> usize {
+        let fields = [
+            (&self.header, self.header_encoding),
+            (&self.code, self.code_encoding),
+            (&self.footer, self.footer_encoding),
+            (&self.input, self.input_encoding),
+        ];
+        let fields: usize = fields
+            .iter()
+            .map(|(text, encoding)| encoding.decoded_len(text))
+            .sum();
+        let args: usize = self.program_arguments.iter().map(String::len).sum();
+        fields + args
+    }
+
+    /// Append a line to the header, after a line break if the header does not
+    /// already end with one. The line is raw bytes, as the backend receives
+    /// it, and the encoding changes like with [`State::set_input`].
+    pub fn push_header_line(&mut self, line: &[u8]) -> Result<(), ValidationError> {
+        let mut header =
+            field_bytes(&self.header, self.header_encoding).map_err(|err| match err {
+                FieldError::Base64(err) => ValidationError::InvalidBase64Header(err),
+                FieldError::Sbcs(c) => ValidationError::UnmappableSbcsHeader(c),
+            })?;
+        // Line feed is `¶` (0x7F) in the SBCS code page
+        let newline = match self.header_encoding {
+            Encoding::Sbcs => 0x7f,
+            _ => b'\n',
+        };
+        if header.last().is_some_and(|&b| b != newline) {
+            header.push(newline);
+        }
+        header.extend_from_slice(line);
+        set_field_bytes(&mut self.header, &mut self.header_encoding, header);
+        Ok(())
+    }
+
+    /// Append raw bytes to the code. The encoding changes like with
+    /// [`State::set_input`].
+    pub fn append_code(&mut self, code: &[u8]) -> Result<(), ValidationError> {
+        let mut bytes = self.code_bytes()?;
+        bytes.extend_from_slice(code);
+        set_field_bytes(&mut self.code, &mut self.code_encoding, bytes);
+        Ok(())
+    }
+
+    /// Set the input to raw bytes. The encoding is kept when it can represent
+    /// the bytes, which SBCS and base64 always can, but UTF-8 switches to
+    /// base64 when the bytes are not valid UTF-8.
+    pub fn set_input(&mut self, input: &[u8]) {
+        set_field_bytes(&mut self.input, &mut self.input_encoding, input.to_vec());
+    }
+
+    /// Clear the input and reset its encoding, for sharing a link without
+    /// private test data.
+    pub fn redact_input(&mut self) {
+        self.input.clear();
+        self.input_encoding = Encoding::default();
+    }
+
+    /// Clear the header, footer, program arguments, and input and reset their
+    /// encodings, leaving only the language, options, and code, which are
+    /// needed to run the code.
+    pub fn redact_all_but_code(&mut self) {
+        self.header.clear();
+        self.header_encoding = Encoding::default();
+        self.footer.clear();
+        self.footer_encoding = Encoding::default();
+        self.program_arguments.clear();
+        self.scalar_program_arguments.clear();
+        self.redact_input();
+    }
+
+    /// Apply a test harness from `template` over this state, keeping the
+    /// language and code. The header, footer, program arguments, and input of
+    /// the template replace those of this state, with their encodings, when
+    /// they are not empty. The options are merged, with those of the template
+    /// first, since both the harness and the code may need flags.
+    pub fn apply_template(&mut self, template: &State) {
+        let mut options = template.options.clone();
+        let mut scalar_options = template.scalar_options.clone();
+        scalar_options.extend(self.scalar_options.iter().map(|i| i + options.len()));
+        options.append(&mut self.options);
+        self.options = options;
+        self.scalar_options = scalar_options;
+        if !template.header.is_empty() {
+            self.header.clone_from(&template.header);
+            self.header_encoding = template.header_encoding;
+        }
+        if !template.footer.is_empty() {
+            self.footer.clone_from(&template.footer);
+            self.footer_encoding = template.footer_encoding;
+        }
+        if !template.program_arguments.is_empty() {
+            self.program_arguments
+                .clone_from(&template.program_arguments);
+            self.scalar_program_arguments
+                .clone_from(&template.scalar_program_arguments);
+        }
+        if !template.input.is_empty() {
+            self.input.clone_from(&template.input);
+            self.input_encoding = template.input_encoding;
+        }
+    }
+
+    /// The link line that ends a Code Golf answer, which links to the code on
+    /// ATO: `[Attempt This Online!](<url>)`, as ATO's template for answers
+    /// writes it.
+    pub fn answer_footer(&self) -> Result<String, EncodeError> {
+        let url = LinkState::from(self.clone()).encode()?;
+        Ok(format!("[Attempt This Online!]({url})"))
+    }
+
+    /// Encode the shortest link for the state, for sharing in QR codes or
+    /// other constrained channels. Encodings of `utf-8` are written empty,
+    /// schema version 0 is used when there are no options or program
+    /// arguments, since it has no fields for them, and the data is compressed
+    /// with the best of [`LinkState::encode_best_effort`]. The link runs the
+    /// same as the one encoded from the [`LinkState`] of the state, but its
+    /// fields are written differently.
+    pub fn encode_short(&self) -> Result<String, EncodeError> {
+        let mut link = LinkState::from(self.clone());
+        for encoding in [
+            &mut link.header_encoding,
+            &mut link.code_encoding,
+            &mut link.footer_encoding,
+            &mut link.input_encoding,
+        ] {
+            if *encoding == Encoding::Utf8.as_str() {
+                encoding.clear();
+            }
+        }
+        // Fails only when version 0 cannot hold the fields
+        let _ = link.set_schema(LinkSchema::V0);
+        Ok(link.encode_best_effort(usize::MAX)?.url)
+    }
+
+    /// A Code Golf answer for the code in Markdown: a heading with the
+    /// language and byte count, the code in a fenced block, and
+    /// [`State::answer_footer`]. The block is highlighted with the Stack
+    /// Exchange class of the language, or not at all when it has none.
+    pub fn to_markdown(&self) -> Result<String, EncodeError> {
+        let bytes = self.code_encoding.decoded_len(&self.code);
+        let mut md = match self.language {
+            Some(l) => format!("# [{}]({}), {bytes} bytes\n\n", l.name(), l.url()),
+            None => format!("# {bytes} bytes\n\n"),
+        };
+        let code = match self.code_encoding {
+            Encoding::Base64 => match field_bytes(&self.code, Encoding::Base64) {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Err(_) => self.code.clone(),
+            },
+            Encoding::Utf8 | Encoding::Sbcs => self.code.clone(),
+        };
+        // The fence must be longer than any run of backticks in the code
+        let mut longest_run = 0;
+        let mut run = 0;
+        for c in code.chars() {
+            run = if c == '`' { run + 1 } else { 0 };
+            longest_run = longest_run.max(run);
+        }
+        let fence = "`".repeat(longest_run.max(2) + 1);
+        let class = self.language.and_then(|l| l.se_class()).unwrap_or_default();
+        md.push_str(&format!("{fence}{class}\n{code}\n{fence}\n\n"));
+        md.push_str(&self.answer_footer()?);
+        Ok(md)
+    }
+
+    /// Convert to a TIO link with the v1 format. TIO has no header, footer,
+    /// or interpreter options in its links, so those must be empty, and the
+    /// language must be in the table of [`tio::tio_language`]. Code in a
+    /// single-byte character set is written as UTF-8, like TIO shows it.
+    pub fn to_tio(&self) -> Result<tio::LinkState, TioConversionError> {
+        let language = self.language.ok_or(TioConversionError::NoLanguage)?;
+        let tio_language = tio::tio_language(language.id())
+            .ok_or_else(|| TioConversionError::UnmappedLanguage(language.id().into()))?;
+        for (field, empty) in [
+            ("options", self.options.is_empty()),
+            ("a header", self.header.is_empty()),
+            ("a footer", self.footer.is_empty()),
+        ] {
+            if !empty {
+                return Err(TioConversionError::UnsupportedField(field));
+            }
+        }
+        let text_bytes = |field, text: &str, encoding| match encoding {
+            Encoding::Base64 => {
+                decode_base64(text).map_err(|err| TioConversionError::InvalidBase64(field, err))
+            }
+            Encoding::Utf8 | Encoding::Sbcs => Ok(text.as_bytes().to_vec()),
+        };
+        Ok(tio::LinkState {
+            schema: tio::LinkSchema::V1,
+            language: tio_language.into(),
+            code: text_bytes("code", &self.code, self.code_encoding)?,
+            input: text_bytes("input", &self.input, self.input_encoding)?,
+            args: self.program_arguments.clone(),
+            ..tio::LinkState::default()
+        })
+    }
+
+    /// Encode links to both ATO and, when the state can be converted, TIO.
+    pub fn share_links(&self) -> ShareLinks {
+        ShareLinks {
+            ato: LinkState::from(self.clone()).encode(),
+            tio: self.to_tio().map(|state| state.encode_v1()),
+        }
+    }
+
+    /// Choose the encodings of the header, code, footer, and input again with
+    /// [`choose_encoding`], for after they have been edited, so the link is
+    /// not rejected. A base64 field is converted to text when its bytes are
+    /// text, and one which no longer decodes, like base64 with text typed into
+    /// it, is taken as text. Text is kept as is and is `sbcs` when the
+    /// language uses a single-byte character set and it is in the code page,
+    /// and `utf-8` otherwise.
+    pub fn fix_encodings(&mut self) {
+        let sbcs = self.language.is_some_and(|l| l.is_sbcs());
+        for (text, encoding) in [
+            (&mut self.header, &mut self.header_encoding),
+            (&mut self.code, &mut self.code_encoding),
+            (&mut self.footer, &mut self.footer_encoding),
+            (&mut self.input, &mut self.input_encoding),
+        ] {
+            if *encoding == Encoding::Base64 {
+                if let Ok(bytes) = field_bytes(text, Encoding::Base64) {
+                    let chosen = choose_encoding(&bytes, self.language);
+                    if chosen != Encoding::Base64 {
+                        *encoding = chosen;
+                        set_field_bytes(text, encoding, bytes);
+                    }
+                    continue;
+                }
+            }
+            *encoding = if sbcs && encode_sbcs(text).is_ok() {
+                Encoding::Sbcs
+            } else {
+                Encoding::Utf8
+            };
+        }
+    }
+
+    /// The bytes represented by the code in its encoding.
+    fn code_bytes(&self) -> Result<Vec<u8>, ValidationError> {
+        field_bytes(&self.code, self.code_encoding).map_err(|err| match err {
+            FieldError::Base64(err) => ValidationError::InvalidBase64Code(err),
+            FieldError::Sbcs(c) => ValidationError::UnmappableSbcsCode(c),
+        })
+    }
+}
+
+/// The MessagePack map body of a request to ATO's `/run` endpoint.
+#[derive(Deserialize)]
+struct RunBody {
+    language: String,
+    #[serde(default)]
+    code: RunBytes,
+    #[serde(default)]
+    input: RunBytes,
+    #[serde(default)]
+    options: Vec<String>,
+    #[serde(default)]
+    arguments: Vec<String>,
+}
+
+/// Bytes in a `/run` body, which clients write as MessagePack binary, but
+/// which may also be a string or an array of integers.
+#[derive(Default)]
+struct RunBytes(Vec<u8>);
+
+impl<'de> Deserialize<'de> for RunBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = RunBytes;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("bytes")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<RunBytes, E> {
+                Ok(RunBytes(v.to_vec()))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<RunBytes, E> {
+                Ok(RunBytes(v.as_bytes().to_vec()))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<RunBytes, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(b) = seq.next_element()? {
+                    bytes.push(b);
+                }
+                Ok(RunBytes(bytes))
+            }
+        }
+
+        deserializer.deserialize_bytes(BytesVisitor)
+    }
+}
+
+/// The bytes represented by a field in its encoding.
+fn field_bytes(text: &str, encoding: Encoding) -> Result<Vec<u8>, FieldError> {
+    match encoding {
+        Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+        Encoding::Sbcs => encode_sbcs(text).map_err(FieldError::Sbcs),
+        Encoding::Base64 => decode_base64(text).map_err(FieldError::Base64),
+    }
+}
+
+/// Set a field to bytes, keeping its encoding unless it is UTF-8 and the bytes
+/// are not valid UTF-8, in which case it switches to base64.
+fn set_field_bytes(text: &mut String, encoding: &mut Encoding, bytes: Vec<u8>) {
+    *text = match *encoding {
+        Encoding::Utf8 => match String::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(err) => {
+                *encoding = Encoding::Base64;
+                BASE64.encode(err.into_bytes())
+            }
+        },
+        Encoding::Sbcs => decode_sbcs(&bytes),
+        Encoding::Base64 => BASE64.encode(bytes),
+    };
 }
 
 // See https://github.com/attempt-this-online/attempt-this-online/blob/b694efd9cfaea87d93827e33ec7f5d812a431833/frontend/components/argvList.tsx
-fn parse_arg_list(args: String) -> Result<Vec<String>, ParseError> {
+/// Parse a JSON array of arguments, returning them and the indices of those
+/// that were scalars other than strings.
+fn parse_arg_list(args: String) -> Result<(Vec<String>, Vec<usize>), ParseError> {
     if args.is_empty() {
-        Ok(Vec::new())
+        Ok((Vec::new(), Vec::new()))
     } else {
-        let values: Vec<serde_json::Value> =
-            serde_json::from_reader(Cursor::new(args.into_bytes()))?;
+        // ATO always encodes options and program arguments as arrays, so
+        // other JSON values are an error, rather than modeled. None of the
+        // links in `tests/ato_links.txt` have a bare flag, like `-e`, instead
+        // of an array, so it is not wrapped in one, but reported as not an
+        // array, rather than as invalid JSON.
+        let values: Vec<&RawValue> = match serde_json::from_str(&args) {
+            Ok(values) => values,
+            Err(err) => match serde_json::from_str(&args) {
+                Ok(value) => return Err(ParseError::OptionsNotArray(value)),
+                Err(_) if !args.trim_start().starts_with('[') => {
+                    return Err(ParseError::OptionsNotArray(args.into()))
+                }
+                Err(_) => return Err(err.into()),
+            },
+        };
         let mut args = Vec::with_capacity(values.len());
-        for v in values {
-            match v {
-                serde_json::Value::Object(_) | serde_json::Value::Array(_) => {
+        let mut scalars = Vec::new();
+        for (i, raw) in values.into_iter().enumerate() {
+            match serde_json::from_str(raw.get())? {
+                serde_json::Value::String(s) => args.push(s),
+                v @ (serde_json::Value::Object(_) | serde_json::Value::Array(_)) => {
                     return Err(ParseError::ObjectArg(v))
                 }
-                _ => args.push(v.to_string()),
+                // Keep the literal text of other scalars, since reformatting
+                // numbers through `f64` would not preserve forms like `1.0`
+                // or `1e3`.
+                _ => {
+                    args.push(raw.get().to_owned());
+                    scalars.push(i);
+                }
+            }
+        }
+        Ok((args, scalars))
+    }
+}
+
+// ATO leaves the field empty, rather than `[]`, when there are no arguments.
+// Otherwise, it uses `JSON.stringify`, which for an array of strings writes no
+// whitespace and escapes only `"`, `\`, and control characters, with `\b`,
+// `\f`, `\n`, `\r`, and `\t` for those with short forms and `\u00xx` (lowercase)
+// for the rest. The compact serde_json serializer writes exactly the same.
+fn encode_arg_list<S: Serialize>(args: &[S]) -> String {
+    if args.is_empty() {
+        String::new()
+    } else {
+        serde_json::to_string(args).unwrap()
+    }
+}
+
+/// Encode arguments like [`encode_arg_list`], but write those at the indices
+/// in `scalars` as their literal text, as [`parse_arg_list`] read them.
+fn encode_arg_list_with_scalars(args: &[String], scalars: &[usize]) -> String {
+    if scalars.is_empty() || args.is_empty() {
+        return encode_arg_list(args);
+    }
+    let mut list = String::from("[");
+    for (i, arg) in args.iter().enumerate() {
+        if i != 0 {
+            list.push(',');
+        }
+        if scalars.contains(&i) {
+            list.push_str(arg);
+        } else {
+            list.push_str(&serde_json::to_string(arg).unwrap());
+        }
+    }
+    list.push(']');
+    list
+}
+
+/// Convert a field in the given encoding to text.
+fn field_text(text: String, encoding: String) -> Result<String, ParseError> {
+    match encoding.try_into()? {
+        Encoding::Utf8 | Encoding::Sbcs => Ok(text),
+        Encoding::Base64 => {
+            let b = decode_base64(&text).map_err(ParseError::InvalidBase64)?;
+            Ok(String::from_utf8_lossy(&b).into_owned())
+        }
+    }
+}
+
+/// Decode base64 text, skipping whitespace like `atob`, since base64 fields
+/// are often wrapped at 76 columns.
+fn decode_base64(s: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    let s: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    BASE64.decode(s)
+}
+
+/// Choose the encoding for a field with the given contents, like the ATO
+/// frontend does: `sbcs` for languages with a single-byte character set, which
+/// can represent any bytes, `utf-8` for other text, and `base64` for binary
+/// data.
+pub fn choose_encoding(bytes: &[u8], language: Option<&Language>) -> Encoding {
+    if language.is_some_and(|l| l.is_sbcs()) {
+        Encoding::Sbcs
+    } else if str::from_utf8(bytes).is_ok() {
+        Encoding::Utf8
+    } else {
+        Encoding::Base64
+    }
+}
+
+impl Encoding {
+    /// All encodings, in the order ATO lists them.
+    pub fn all() -> &'static [Encoding] {
+        &[Encoding::Utf8, Encoding::Sbcs, Encoding::Base64]
+    }
+
+    /// The name of the encoding in a link.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Sbcs => "sbcs",
+            Encoding::Base64 => "base64",
+        }
+    }
+
+    /// The number of bytes represented by text in this encoding.
+    fn decoded_len(self, text: &str) -> usize {
+        match self {
+            Encoding::Utf8 => text.len(),
+            Encoding::Sbcs => text.chars().count(),
+            Encoding::Base64 => {
+                let digits = text
+                    .bytes()
+                    .filter(|&b| !b.is_ascii_whitespace() && b != b'=')
+                    .count();
+                digits * 3 / 4
             }
         }
-        Ok(args)
     }
 }
 
@@ -98,3 +996,761 @@ impl TryFrom<String> for Encoding {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scalar_args() {
+        let (args, scalars) = parse_arg_list(r#"[1, 1.0, true, "x"]"#.into()).unwrap();
+        assert_eq!(args, ["1", "1.0", "true", "x"]);
+        assert_eq!(scalars, [0, 1, 2]);
+        let (args, scalars) = parse_arg_list(r#"[1e3,1.50,-0,null,"\"1\""]"#.into()).unwrap();
+        assert_eq!(args, ["1e3", "1.50", "-0", "null", "\"1\""]);
+        assert_eq!(scalars, [0, 1, 2, 3]);
+
+        let state = LinkState {
+            options: r#"[1, 1.0, true, "x"]"#.into(),
+            program_arguments: r#"[1,1.0,true,"x"]"#.into(),
+            ..LinkState::default()
+        };
+        let decoded = LinkState::decode(&state.encode().unwrap()).unwrap();
+        assert_eq!(state.options, decoded.options);
+        assert_eq!(state.program_arguments, decoded.program_arguments);
+        let parsed = decoded.parse_with(|_| None).unwrap();
+        assert_eq!(parsed.options, ["1", "1.0", "true", "x"]);
+        assert_eq!(*parsed.program_arguments, ["1", "1.0", "true", "x"]);
+        let reencoded = LinkState::from(parsed);
+        assert_eq!(reencoded.options, r#"[1,1.0,true,"x"]"#);
+        assert_eq!(reencoded.program_arguments, r#"[1,1.0,true,"x"]"#);
+
+        // A string that looks like a scalar is still a string
+        let state = LinkState {
+            options: r#"["1",1]"#.into(),
+            ..LinkState::default()
+        };
+        let mut parsed = state.clone().parse_with(|_| None).unwrap();
+        assert_eq!(LinkState::from(parsed.clone()).options, state.options);
+        parsed.apply_template(&parsed.clone());
+        assert_eq!(parsed.scalar_options, [1, 3]);
+        assert_eq!(LinkState::from(parsed).options, r#"["1",1,"1",1]"#);
+    }
+
+    #[test]
+    fn not_array() {
+        let err = parse_arg_list(r#"{"k":"v"}"#.into()).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::OptionsNotArray(v) if v["k"] == "v"),
+            "{err:?}",
+        );
+        assert!(matches!(
+            parse_arg_list(r#""-e""#.into()),
+            Err(ParseError::OptionsNotArray(_)),
+        ));
+        assert!(matches!(
+            parse_arg_list(r#"["-e""#.into()),
+            Err(ParseError::InvalidJson(_)),
+        ));
+        let err = parse_arg_list("-e".into()).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::OptionsNotArray(v) if v == "-e"),
+            "{err:?}",
+        );
+        assert_eq!(err.to_string(), r#"arguments are not a JSON array: "-e""#);
+        let state = LinkState {
+            options: "-e".into(),
+            ..LinkState::new()
+        };
+        assert!(matches!(
+            state.parse_with(|_| None),
+            Err(ParseError::OptionsNotArray(_)),
+        ));
+    }
+
+    #[test]
+    fn set_args() {
+        let mut state = LinkState::new();
+        state.set_options(&["-e"]);
+        assert_eq!(state.options, r#"["-e"]"#);
+        state.set_program_arguments(&["1", "say \"hi\"\n", ""]);
+        assert_eq!(state.program_arguments, r#"["1","say \"hi\"\n",""]"#);
+        state.set_options(&[]);
+        assert_eq!(state.options, "");
+        let parsed = state.parse_with(|_| None).unwrap();
+        assert_eq!(*parsed.program_arguments, ["1", "say \"hi\"\n", ""]);
+    }
+
+    #[test]
+    fn parse_unchecked() {
+        let mut state = LinkState::new();
+        state.language = "not_a_language".into();
+        state.set_options(&["-e"]);
+        state.code = "p".into();
+        assert!(matches!(
+            state.clone().parse_with(|_| None),
+            Err(ParseError::InvalidLanguage(l)) if l == "not_a_language",
+        ));
+        let parsed = state.parse_unchecked().unwrap();
+        assert_eq!(parsed.language, None);
+        assert_eq!(parsed.options, ["-e"]);
+        assert_eq!(parsed.code, "p");
+    }
+
+    #[test]
+    fn from_se_block() {
+        let python = Language::test_language("python");
+        let jelly = Language::test_language("jelly");
+        let lookup = |class: &str| match class {
+            "lang-py" => Some(python),
+            "lang-jelly" => Some(jelly),
+            _ => None,
+        };
+
+        let state = LinkState::from_se_block_with("lang-py", "print(1)\n", lookup).unwrap();
+        assert_eq!(state.language, "python");
+        assert_eq!(state.code, "print(1)\n");
+        assert_eq!(state.code_encoding, "utf-8");
+        let state = LinkState::from_se_block_with("lang-jelly", "“½ṭ»", lookup).unwrap();
+        assert_eq!(state.code_encoding, "sbcs");
+        assert_eq!(
+            LinkState::from_se_block_with("lang-c", "int main;", lookup),
+            None,
+        );
+    }
+
+    #[test]
+    fn with_language() {
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            code_encoding: "utf-8".into(),
+            options: r#"["-O"]"#.into(),
+            ..LinkState::new()
+        };
+        let swapped = state.clone().with_language("python3");
+        assert_eq!(swapped.language, "python3");
+        assert_eq!(
+            LinkState {
+                language: "python".into(),
+                ..swapped.clone()
+            },
+            state,
+        );
+        let decoded = LinkState::decode(&swapped.encode().unwrap()).unwrap();
+        assert_eq!(decoded, swapped);
+
+        let languages: &'static _ = Box::leak(Box::new(Language::test_languages(&["python2"])));
+        let lookup = |id: &str| languages.get(id);
+        let swapped = state
+            .clone()
+            .with_language_checked_with("python2", lookup)
+            .unwrap();
+        assert_eq!(swapped, state.clone().with_language("python2"));
+        assert!(matches!(
+            state.with_language_checked_with("python3", lookup),
+            Err(ParseError::InvalidLanguage(l)) if l == "python3",
+        ));
+    }
+
+    #[test]
+    fn canonicalize_arg_lists() {
+        let mut state = LinkState {
+            options: r#"[ "1", "2" ]"#.into(),
+            program_arguments: "[\n  \"\\u0041\",\n  \"\\/\"\n]".into(),
+            ..LinkState::new()
+        };
+        state.canonicalize_arg_lists().unwrap();
+        assert_eq!(state.options, r#"["1","2"]"#);
+        assert_eq!(state.program_arguments, r#"["A","/"]"#);
+
+        // These match `JSON.stringify` in JavaScript
+        let args = [
+            "\u{8}\t\n\u{c}\r",
+            "\u{0}\u{1f}\u{7f}",
+            "\"\\/",
+            "é\u{2028}",
+        ];
+        assert_eq!(
+            encode_arg_list(&args),
+            "[\"\\b\\t\\n\\f\\r\",\"\\u0000\\u001f\u{7f}\",\"\\\"\\\\/\",\"é\u{2028}\"]",
+        );
+    }
+
+    #[test]
+    fn into_code() {
+        let state = LinkState {
+            header: "x = 1".into(),
+            header_encoding: "utf-8".into(),
+            code: "“x”Ṅ".into(),
+            code_encoding: "sbcs".into(),
+            footer: "cHJpbnQoeCkK/w==".into(),
+            footer_encoding: "base64".into(),
+            ..LinkState::default()
+        };
+        assert_eq!(
+            state.into_code().unwrap(),
+            "x = 1\n“x”Ṅ\nprint(x)\n\u{fffd}"
+        );
+
+        let state = LinkState {
+            code: "print(1)".into(),
+            ..LinkState::default()
+        };
+        assert_eq!(state.into_code().unwrap(), "print(1)");
+
+        let state = LinkState {
+            code: "print(1)".into(),
+            code_encoding: "base64".into(),
+            ..LinkState::default()
+        };
+        assert!(matches!(
+            state.into_code(),
+            Err(ParseError::InvalidBase64(_)),
+        ));
+    }
+
+    #[test]
+    fn total_bytes() {
+        let state = State {
+            header: "é".into(),
+            header_encoding: Encoding::Utf8,
+            code: "“x”Ṅ".into(),
+            code_encoding: Encoding::Sbcs,
+            footer: "cHJp\nbnQ=".into(),
+            footer_encoding: Encoding::Base64,
+            program_arguments: Args(vec!["ab".into(), "".into()]),
+            input: "1\n2".into(),
+            ..State::default()
+        };
+        assert_eq!(state.total_bytes(), 2 + 4 + 5 + 3 + 2);
+        assert_eq!(State::default().total_bytes(), 0);
+    }
+
+    #[test]
+    fn from_parts() {
+        let state = LinkState::from_parts(
+            "jelly",
+            ("", Encoding::Utf8),
+            ("“x”Ṅ", Encoding::Sbcs),
+            ("cHJpbnQoMSk=", Encoding::Base64),
+            ("1\n2", Encoding::Utf8),
+        )
+        .unwrap();
+        assert_eq!(state.language, "jelly");
+        assert_eq!(state.code, "“x”Ṅ");
+        assert_eq!(state.code_encoding, "sbcs");
+        assert_eq!(state.footer_encoding, "base64");
+        assert_eq!(state.input_encoding, "utf-8");
+        let decoded = LinkState::decode(&state.encode().unwrap()).unwrap();
+        assert_eq!(state, decoded);
+
+        assert!(matches!(
+            LinkState::from_parts(
+                "python",
+                ("", Encoding::Utf8),
+                ("print(1)", Encoding::Base64),
+                ("", Encoding::Utf8),
+                ("", Encoding::Utf8),
+            ),
+            Err(ParseError::InvalidBase64(_)),
+        ));
+        assert!(matches!(
+            LinkState::from_parts(
+                "jelly",
+                ("", Encoding::Utf8),
+                ("😀", Encoding::Sbcs),
+                ("", Encoding::Utf8),
+                ("", Encoding::Utf8),
+            ),
+            Err(ParseError::UnmappableSbcs('😀')),
+        ));
+    }
+
+    #[test]
+    fn redact() {
+        let state = State {
+            options: vec!["-O".into()],
+            header: "import sys".into(),
+            code: "cHJpbnQoMSk=".into(),
+            code_encoding: Encoding::Base64,
+            footer: "Pz8/".into(),
+            footer_encoding: Encoding::Base64,
+            program_arguments: Args(vec!["secret".into()]),
+            input: "private".into(),
+            input_encoding: Encoding::Sbcs,
+            ..State::default()
+        };
+
+        let mut redacted = state.clone();
+        redacted.redact_input();
+        assert_eq!(
+            redacted,
+            State {
+                input: "".into(),
+                input_encoding: Encoding::Utf8,
+                ..state.clone()
+            },
+        );
+
+        redacted.redact_all_but_code();
+        let expected = State {
+            options: vec!["-O".into()],
+            code: "cHJpbnQoMSk=".into(),
+            code_encoding: Encoding::Base64,
+            ..State::default()
+        };
+        assert_eq!(redacted, expected);
+
+        let link = LinkState::from(redacted);
+        assert_eq!(
+            link,
+            LinkState {
+                options: r#"["-O"]"#.into(),
+                header_encoding: "utf-8".into(),
+                code: "cHJpbnQoMSk=".into(),
+                code_encoding: "base64".into(),
+                footer_encoding: "utf-8".into(),
+                input_encoding: "utf-8".into(),
+                ..LinkState::default()
+            },
+        );
+        let decoded = LinkState::decode(&link.encode().unwrap()).unwrap();
+        assert_eq!(expected, decoded.parse_with(|_| None).unwrap());
+    }
+
+    #[test]
+    fn mutators() {
+        let mut state = State::default();
+        state.push_header_line(b"import sys").unwrap();
+        state.push_header_line(b"import os").unwrap();
+        assert_eq!(state.header, "import sys\nimport os");
+        assert_eq!(state.header_encoding, Encoding::Utf8);
+
+        state.append_code(b"print(1)").unwrap();
+        state.append_code(b"\xff").unwrap();
+        assert_eq!(state.code, "cHJpbnQoMSn/");
+        assert_eq!(state.code_encoding, Encoding::Base64);
+        state.append_code(b"\n").unwrap();
+        assert_eq!(state.code, "cHJpbnQoMSn/Cg==");
+
+        state.set_input(b"1 2");
+        assert_eq!(state.input, "1 2");
+        assert_eq!(state.input_encoding, Encoding::Utf8);
+        state.set_input(&[0xc3]);
+        assert_eq!(state.input, "ww==");
+        assert_eq!(state.input_encoding, Encoding::Base64);
+
+        let mut state = State {
+            header: "“x”".into(),
+            header_encoding: Encoding::Sbcs,
+            code: "😀".into(),
+            code_encoding: Encoding::Sbcs,
+            ..State::default()
+        };
+        state.push_header_line(&[0xfe]).unwrap();
+        assert_eq!(state.header, "“x”\n“");
+        assert!(matches!(
+            state.append_code(b"1"),
+            Err(ValidationError::UnmappableSbcsCode('😀')),
+        ));
+    }
+
+    #[test]
+    fn validate_base64() {
+        // The code from the progress pride flag link in `ato::link::tests`
+        let mut state = State {
+            code: "Y2hhcipyPSL/AAD/jAD/8AAAiigAUP94AIz/////////tMhu3PBkMhQAAAAiO2ksajttYWluKHgp\ne3dyaXRlKDEsIlA2IDEwNTkgNjcyIDI1NSAiLDE2KTtmb3IoO2k8NjcyOysraSlmb3Ioaj0wO2o8\nMTA1OTsrK2opd3JpdGUoMSxyKygoeD1hYnMoaS0zMzYpK2opPDUwND82K3gvODQ6aS8xMTIpKjMs\nMyk7fQo=".into(),
+            code_encoding: Encoding::Base64,
+            ..State::default()
+        };
+        state.validate().unwrap();
+        state.code = "print(1)".into();
+        assert!(matches!(
+            state.validate(),
+            Err(ValidationError::InvalidBase64Code(_)),
+        ));
+        state.code_encoding = Encoding::Utf8;
+        state.validate().unwrap();
+    }
+
+    #[test]
+    fn base64_line_breaks() {
+        // Base64 from ATO is wrapped every 76 characters, which `atob` and the
+        // decoder here ignore
+        let wrapped = "Y2hhcipyPSL/AAD/jAD/8AAAiigAUP94AIz/////////tMhu3PBkMhQAAAAiO2ksajttYWluKHgp\ne3dyaXRlKDEsIlA2IDEwNTkgNjcyIDI1NSAiLDE2KTtmb3IoO2k8NjcyOysraSlmb3Ioaj0wO2o8\r\nMTA1OTsrK2opd3JpdGUoMSxyKygoeD1hYnMoaS0zMzYpK2opPDUwND82K3gvODQ6aS8xMTIpKjMs\nMyk7fQo=";
+        let bytes = decode_base64(wrapped).unwrap();
+        assert_eq!(
+            bytes,
+            BASE64.decode(wrapped.replace(['\r', '\n'], "")).unwrap()
+        );
+        assert!(bytes.starts_with(b"char*r=\"\xff\0\0\xff"));
+        assert!(bytes.ends_with(b"write(1,r+((x=abs(i-336)+j)<504?6+x/84:i/112)*3,3);}\n"));
+        let link = LinkState {
+            code: wrapped.into(),
+            code_encoding: "base64".into(),
+            ..LinkState::new()
+        };
+        assert_eq!(link.code_text_bytes(), Some(bytes.clone()));
+        let state = link.parse_with(|_| None).unwrap();
+        assert_eq!(state.code_bytes().unwrap(), bytes);
+        assert_eq!(state.total_bytes(), bytes.len());
+    }
+
+    #[test]
+    fn validate_sbcs() {
+        // The code from the Jelly link in `ato::link::tests`
+        let mut state = State {
+            code: "“⁾ḋḷ?D8=ƙʂȤ£²^ĖẸI)¡e[µ°⁾ṢJ*MĠɓ¤Ḃġ⁽vEEɲạ®G{(ŀ³⁻¹ƭTẸⱮ’ṃ“ȥ3’Ọ\nØaż¢FyⱮ".into(),
+            code_encoding: Encoding::Sbcs,
+            ..State::default()
+        };
+        state.validate().unwrap();
+        assert!(!state.code_is_valid_utf8());
+        state.code = "print(1)¶".into();
+        assert!(state.code_is_valid_utf8());
+        state.code = "print(1) # 😀".into();
+        assert!(matches!(
+            state.validate(),
+            Err(ValidationError::UnmappableSbcsCode('😀')),
+        ));
+        assert!(!state.code_is_valid_utf8());
+
+        state.code = "cHJpbnQoMSk=".into();
+        state.code_encoding = Encoding::Base64;
+        assert!(state.code_is_valid_utf8());
+        state.code = "/w==".into();
+        assert!(!state.code_is_valid_utf8());
+    }
+
+    #[test]
+    fn encoding_names() {
+        for &encoding in Encoding::all() {
+            let name = encoding.as_str().to_owned();
+            assert_eq!(Encoding::try_from(name).unwrap(), encoding);
+        }
+    }
+
+    #[test]
+    fn from_tio_args() {
+        let tio = tio::LinkState {
+            language: "dc".into(),
+            code: b"?p".to_vec(),
+            args: Args(vec!["--".into(), "-6".into()]),
+            ..tio::LinkState::new()
+        };
+        let state = LinkState::from(&tio);
+        assert_eq!(state.program_arguments, r#"["--","-6"]"#);
+        assert_eq!(
+            parse_arg_list(state.program_arguments).unwrap().0,
+            tio.args.0
+        );
+        assert_eq!(state.code, "?p");
+        assert_eq!(state.code_encoding, "utf-8");
+        assert_eq!(state.options, "");
+    }
+
+    #[test]
+    fn answer_footer() {
+        let state = State {
+            code: "print(1)".into(),
+            ..State::default()
+        };
+        let url = LinkState::from(state.clone()).encode().unwrap();
+        assert_eq!(
+            state.answer_footer().unwrap(),
+            format!("[Attempt This Online!]({url})"),
+        );
+    }
+
+    #[test]
+    fn share_links() {
+        let python = Language::test_language("python");
+        let state = State {
+            language: Some(python),
+            code: "print(input())".into(),
+            input: "AQI".into(),
+            input_encoding: Encoding::Base64,
+            program_arguments: Args(vec!["a b".into()]),
+            ..State::default()
+        };
+        let links = state.share_links();
+        assert_eq!(
+            links.ato.unwrap(),
+            LinkState::from(state.clone()).encode().unwrap(),
+        );
+        assert_eq!(
+            links.tio.unwrap(),
+            "https://tio.run/#python3#code=cHJpbnQoaW5wdXQoKSk&input=AQI&args=YSBi",
+        );
+
+        // ATO wraps base64 at 76 columns
+        let wrapped = State {
+            input: "AQID\nBAUG".into(),
+            ..state.clone()
+        };
+        assert_eq!(wrapped.to_tio().unwrap().input, b"\x01\x02\x03\x04\x05\x06");
+
+        let state = State {
+            header: "import sys".into(),
+            ..state
+        };
+        let links = state.share_links();
+        assert!(links.ato.is_ok());
+        assert!(matches!(
+            links.tio,
+            Err(TioConversionError::UnsupportedField("a header")),
+        ));
+        assert!(matches!(
+            State::default().to_tio(),
+            Err(TioConversionError::NoLanguage),
+        ));
+    }
+
+    #[test]
+    fn encode_short() {
+        let state = State {
+            code: "print(sum(map(int,input().split())))".into(),
+            input: "1 2 3".into(),
+            ..State::default()
+        };
+        let url = LinkState::from(state.clone()).encode().unwrap();
+        let short = state.encode_short().unwrap();
+        assert!(short.len() < url.len(), "{short} is not shorter than {url}");
+        assert!(short.starts_with("https://ato.pxeger.com/run?0="));
+        let decoded = LinkState::decode(&short).unwrap();
+        assert_eq!(decoded.code_encoding, "");
+        assert!(decoded.semantically_eq(&LinkState::decode(&url).unwrap()));
+
+        let state = State {
+            program_arguments: Args(vec!["-n".into()]),
+            ..state
+        };
+        let short = state.encode_short().unwrap();
+        assert!(short.starts_with("https://ato.pxeger.com/run?1="));
+        let decoded = LinkState::decode(&short).unwrap();
+        assert_eq!(decoded.program_arguments, r#"["-n"]"#);
+    }
+
+    #[test]
+    fn to_markdown() {
+        let languages: &'static _ =
+            Box::leak(Box::new(Language::test_languages(&["python", "knight"])));
+        let state = State {
+            language: Some(&languages["python"]),
+            code: "print(1)".into(),
+            ..State::default()
+        };
+        assert_eq!(
+            state.to_markdown().unwrap(),
+            format!(
+                "# [Python](https://www.python.org), 8 bytes\n\n```lang-py\nprint(1)\n```\n\n{}",
+                state.answer_footer().unwrap(),
+            ),
+        );
+        let state = State {
+            language: Some(&languages["knight"]),
+            code: "O```".into(),
+            ..State::default()
+        };
+        assert_eq!(
+            state.to_markdown().unwrap(),
+            format!(
+                "# [Knight](https://github.com/knight-lang/knight-lang), 4 bytes\n\n````\nO```\n````\n\n{}",
+                state.answer_footer().unwrap(),
+            ),
+        );
+    }
+
+    #[test]
+    fn apply_template() {
+        let template = State {
+            options: vec!["-O".into()],
+            header: "f=\\".into(),
+            footer: "print(f(2))".into(),
+            program_arguments: Args(vec!["x".into()]),
+            code: "ignored".into(),
+            ..State::default()
+        };
+        let mut state = State {
+            options: vec!["-W".into()],
+            code: "lambda n:n*2".into(),
+            input: "kept".into(),
+            input_encoding: Encoding::Sbcs,
+            ..State::default()
+        };
+        state.apply_template(&template);
+        assert_eq!(
+            state,
+            State {
+                options: vec!["-O".into(), "-W".into()],
+                header: "f=\\".into(),
+                footer: "print(f(2))".into(),
+                program_arguments: Args(vec!["x".into()]),
+                code: "lambda n:n*2".into(),
+                input: "kept".into(),
+                input_encoding: Encoding::Sbcs,
+                ..State::default()
+            },
+        );
+    }
+
+    #[test]
+    fn choose_encoding() {
+        let python = Some(Language::test_language("python"));
+        let jelly = Some(Language::test_language("jelly"));
+        assert_eq!(super::choose_encoding(b"print(1)", None), Encoding::Utf8);
+        assert_eq!(super::choose_encoding(b"print(1)", python), Encoding::Utf8);
+        assert_eq!(super::choose_encoding(b"\xff", python), Encoding::Base64);
+        assert_eq!(super::choose_encoding(b"\xff", jelly), Encoding::Sbcs);
+        assert_eq!(super::choose_encoding(b"1+2", jelly), Encoding::Sbcs);
+    }
+
+    #[test]
+    fn is_runnable() {
+        let python = Language::test_language("python");
+        let lookup = |id: &str| (id == "python").then_some(python);
+        let state = LinkState {
+            language: "python".into(),
+            code: "print(1)".into(),
+            code_encoding: "utf-8".into(),
+            program_arguments: r#"["a"]"#.into(),
+            ..LinkState::new()
+        };
+        state.is_runnable_with(lookup).unwrap();
+
+        let state = LinkState {
+            language: "pyhton".into(),
+            header: "!!".into(),
+            header_encoding: "base64".into(),
+            code: "✓".into(),
+            code_encoding: "sbcs".into(),
+            input_encoding: "latin-1".into(),
+            options: "-O".into(),
+            ..state
+        };
+        let problems = state.is_runnable_with(lookup).unwrap_err();
+        let fields: Vec<_> = problems.iter().map(|p| p.field).collect();
+        assert_eq!(fields, ["language", "options", "header", "code", "input"]);
+        assert!(matches!(
+            &problems[0].error,
+            ParseError::InvalidLanguage(l) if l == "pyhton",
+        ));
+        assert!(matches!(problems[1].error, ParseError::OptionsNotArray(_)));
+        assert!(matches!(problems[2].error, ParseError::InvalidBase64(_)));
+        assert!(matches!(problems[3].error, ParseError::UnmappableSbcs('✓')));
+        assert!(matches!(
+            &problems[4].error,
+            ParseError::InvalidEncoding(e) if e == "latin-1",
+        ));
+    }
+
+    #[test]
+    fn fix_encodings() {
+        let languages: &'static _ =
+            Box::leak(Box::new(Language::test_languages(&["python", "jelly"])));
+        let mut state = State {
+            language: Some(&languages["python"]),
+            header: "/w".into(),
+            header_encoding: Encoding::Base64,
+            code: "print(input())".into(),
+            code_encoding: Encoding::Base64,
+            input: "aGk".into(),
+            input_encoding: Encoding::Base64,
+            ..State::default()
+        };
+        state.fix_encodings();
+        assert_eq!(state.header, "/w");
+        assert_eq!(state.header_encoding, Encoding::Base64);
+        assert_eq!(state.code, "print(input())");
+        assert_eq!(state.code_encoding, Encoding::Utf8);
+        assert_eq!(state.input, "hi");
+        assert_eq!(state.input_encoding, Encoding::Utf8);
+
+        let mut state = State {
+            language: Some(&languages["jelly"]),
+            header: "✓".into(),
+            header_encoding: Encoding::Sbcs,
+            code: "“¡ȷ»".into(),
+            ..State::default()
+        };
+        state.fix_encodings();
+        assert_eq!(state.header, "✓");
+        assert_eq!(state.header_encoding, Encoding::Utf8);
+        assert_eq!(state.code, "“¡ȷ»");
+        assert_eq!(state.code_encoding, Encoding::Sbcs);
+    }
+
+    #[test]
+    fn semantically_eq() {
+        let v0 = LinkState {
+            schema: LinkSchema::V0,
+            language: "python".into(),
+            code: "print(1)".into(),
+            code_encoding: "utf-8".into(),
+            ..LinkState::new()
+        };
+        let v1 = LinkState {
+            schema: LinkSchema::V1,
+            options: "[]".into(),
+            code_encoding: "".into(),
+            extra_query: vec![("x".into(), "1".into())],
+            ..v0.clone()
+        };
+        assert_ne!(v0, v1);
+        assert!(v0.semantically_eq(&v1));
+        let decoded = LinkState::decode(&v0.encode().unwrap()).unwrap();
+        assert!(decoded.semantically_eq(&v1));
+
+        let other_code = LinkState {
+            code: "print(2)".into(),
+            ..v1.clone()
+        };
+        assert!(!v0.semantically_eq(&other_code));
+        let other_language = LinkState {
+            language: "python2".into(),
+            ..v1.clone()
+        };
+        assert!(!v0.semantically_eq(&other_language));
+        let invalid = LinkState {
+            code_encoding: "utf-16".into(),
+            ..v1
+        };
+        assert!(!invalid.semantically_eq(&invalid));
+    }
+
+    #[test]
+    fn from_run_body() {
+        let python = Language::test_language("python");
+        let lookup = |id: &str| (id == "python").then_some(python);
+        // A hand-built body, shaped like the ones the `curl` examples in ATO's
+        // API docs send, with the binary fields ATO's own clients write
+        let body = b"\x86\xa8language\xa6python\xa4code\xc4\x0eprint(input())\xa5input\xc4\x03hi\n\xa9arguments\x91\xc4\x02-x\xa7options\x91\xc4\x02-u\xa7timeout<";
+        assert_eq!(
+            State::from_run_body_with(body, lookup).unwrap(),
+            State {
+                language: Some(python),
+                options: vec!["-u".into()],
+                code: "print(input())".into(),
+                program_arguments: Args(vec!["-x".into()]),
+                input: "hi\n".into(),
+                ..State::default()
+            },
+        );
+
+        let body = b"\x82\xa8language\xa6python\xa4code\xc4\x02\xff\x00";
+        let state = State::from_run_body_with(body, lookup).unwrap();
+        assert_eq!(state.code, "/wA=");
+        assert_eq!(state.code_encoding, Encoding::Base64);
+
+        let body = b"\x81\xa8language\xa6pyhton";
+        assert!(matches!(
+            State::from_run_body_with(body, lookup),
+            Err(RunBodyError::InvalidLanguage(l)) if l == "pyhton",
+        ));
+        assert!(matches!(
+            State::from_run_body_with(b"\xa6python", lookup),
+            Err(RunBodyError::MessagePack(_)),
+        ));
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt::{self, Display, Formatter};
+
+/// The results of checking that a corpus of share links roundtrips.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CorpusReport {
+    /// The number of links checked.
+    pub total: usize,
+    /// The number of links that re-encoded to exactly the same URL.
+    pub exact: usize,
+    /// The number of links that re-encoded to a different URL, but with
+    /// identical data, e.g., due to differences in compression.
+    pub encoding_differs: usize,
+    /// The links that could not be roundtripped.
+    pub failures: Vec<CorpusFailure>,
+}
+
+/// A link that could not be roundtripped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorpusFailure {
+    pub link: String,
+    pub reason: String,
+}
+
+impl CorpusReport {
+    /// Reports whether every link roundtripped.
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Display for CorpusReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}/{} links roundtrip exactly", self.exact, self.total)?;
+        writeln!(
+            f,
+            "{}/{} links re-encode differently",
+            self.encoding_differs, self.total,
+        )?;
+        write!(f, "{}/{} links failed", self.failures.len(), self.total)?;
+        for failure in &self.failures {
+            write!(f, "\n  {}: {}", failure.link, failure.reason)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use std::ops::Range;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// The site that a share link is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Platform {
+    /// Attempt This Online
+    Ato,
+    /// Try It Online, on any of its domains
+    Tio,
+}
+
+/// Get the site of a domain.
+pub(crate) fn host_platform(domain: &str) -> Option<Platform> {
+    match domain {
+        "ato.pxeger.com" => Some(Platform::Ato),
+        "tio.run" | "tryitonline.net" => Some(Platform::Tio),
+        domain if domain.ends_with(".tryitonline.net") => Some(Platform::Tio),
+        _ => None,
+    }
+}
+
+/// A share link found in text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtractedLink<'a> {
+    /// The site that the link is for.
+    pub platform: Platform,
+    /// The byte range of the link in the text.
+    pub range: Range<usize>,
+    /// The link.
+    pub link: &'a str,
+}
+
+/// Find the ATO and TIO share links in arbitrary text, such as Markdown, HTML,
+/// or chat logs, in order. Only links to the hosts that the decoders accept
+/// are matched, so they can be passed to the decoders directly.
+///
+/// Punctuation which ends a sentence or closes a Markdown link, like `.` or
+/// `)`, is not included in a link, unless a parenthesis is balanced within
+/// it.
+pub fn extract_links(text: &str) -> Vec<ExtractedLink<'_>> {
+    lazy_static! {
+        static ref LINK: Regex = Regex::new(
+            r#"(?i)\bhttps?://([a-z0-9-]+(?:\.[a-z0-9-]+)+)([/?#][^\s<>"'`\[\]{}|\\^]*)?"#
+        )
+        .unwrap();
+    }
+    LINK.captures_iter(text)
+        .filter_map(|caps| {
+            let m = caps.get(0).unwrap();
+            let platform = host_platform(&caps[1].to_ascii_lowercase())?;
+            // ATO only shares links to /run
+            if platform == Platform::Ato && !is_run_path(caps.get(2).map_or("", |p| p.as_str())) {
+                return None;
+            }
+            let link = trim_trailing_punctuation(m.as_str());
+            Some(ExtractedLink {
+                platform,
+                range: m.start()..m.start() + link.len(),
+                link,
+            })
+        })
+        .collect()
+}
+
+/// Reports whether the path of an ATO link is `/run`, and not a longer path
+/// like `/runner`.
+fn is_run_path(path: &str) -> bool {
+    path.get(..4)
+        .is_some_and(|run| run.eq_ignore_ascii_case("/run"))
+        && !path[4..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Trim punctuation that is more likely to belong to the surrounding text
+/// than to the link.
+fn trim_trailing_punctuation(mut link: &str) -> &str {
+    loop {
+        let trimmed = link.trim_end_matches(['.', ',', ';', ':', '!', '?', '*']);
+        // A closing parenthesis is part of the link only when it has an
+        // opening parenthesis in the link
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(rest) if trimmed.matches(')').count() > trimmed.matches('(').count() => rest,
+            _ => trimmed,
+        };
+        if trimmed.len() == link.len() {
+            return link;
+        }
+        link = trimmed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract() {
+        let text = "\
+[Attempt This Online!](https://ato.pxeger.com/run?1=m72khQ) and
+<a href=\"https://tio.run/##K8jILM5ITVGwUtBUSVPIKM3NBQA\">TIO</a>,
+see https://tio.run/#sed#code=cC8&input=. Also (https://python3.tryitonline.net/#code=cHJpbnQoMSk&input=),
+but not https://example.com/run?1=abc or https://tio.run.example.com/ or
+https://ato.pxeger.com/runner or https://tio.nexus/sed or
+https://ato.pxeger.com/ or http://tryitonline.net/#code=&input=.";
+        let links = extract_links(text);
+        let found: Vec<_> = links.iter().map(|l| (l.platform, l.link)).collect();
+        assert_eq!(
+            found,
+            [
+                (Platform::Ato, "https://ato.pxeger.com/run?1=m72khQ"),
+                (
+                    Platform::Tio,
+                    "https://tio.run/##K8jILM5ITVGwUtBUSVPIKM3NBQA"
+                ),
+                (Platform::Tio, "https://tio.run/#sed#code=cC8&input="),
+                (
+                    Platform::Tio,
+                    "https://python3.tryitonline.net/#code=cHJpbnQoMSk&input=",
+                ),
+                (Platform::Tio, "http://tryitonline.net/#code=&input="),
+            ],
+        );
+        for link in &links {
+            assert_eq!(&text[link.range.clone()], link.link);
+        }
+    }
+
+    #[test]
+    fn balanced_parens() {
+        let links = extract_links("(see https://ato.pxeger.com/run?L=a(b))");
+        assert_eq!(links[0].link, "https://ato.pxeger.com/run?L=a(b)");
+    }
+}
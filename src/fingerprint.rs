@@ -0,0 +1,134 @@
+use crate::{
+    any::AnyLinkState,
+    tio::{self, ato_language},
+};
+
+/// What a link runs, for finding links that run the same code in the same
+/// language, even across sites. Links with equal fingerprints run the same code
+/// in the same language, but may differ in their input or other fields.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Fingerprint {
+    /// The ATO id of the language. TIO languages with no known ATO id are
+    /// prefixed with `tio:`, so they do not collide with ATO ids.
+    pub language: String,
+    /// The code as text, with line endings normalized to `\n` and trailing
+    /// whitespace removed. Code that is not text, like ATO's `base64`
+    /// encoding, is its decoded bytes.
+    pub code: Vec<u8>,
+}
+
+/// Compute the fingerprint of a link from its code and language. The header,
+/// footer, arguments, and input are ignored, since they are usually test
+/// scaffolding.
+///
+/// ATO's `sbcs` encoding is compared by its text, since SBCS languages on TIO
+/// encode their code page as UTF-8.
+pub fn semantic_fingerprint(state: &AnyLinkState) -> Fingerprint {
+    let (language, code) = match state {
+        AnyLinkState::Ato(state) => (
+            state.language.clone(),
+            state
+                .code_text_bytes()
+                .unwrap_or_else(|| state.code.clone().into_bytes()),
+        ),
+        AnyLinkState::Tio(state) => {
+            // TIO v1 language ids are the same on tio.run, so links on either
+            // domain match
+            let language = match ato_language(&state.language) {
+                Some(language) => language.to_owned(),
+                None => format!("tio:{}", tio::resolve_language(&state.language)),
+            };
+            (language, state.code.clone())
+        }
+    };
+    Fingerprint {
+        language,
+        code: normalize_code(&code),
+    }
+}
+
+/// Normalize line endings to `\n` and remove trailing whitespace.
+fn normalize_code(code: &[u8]) -> Vec<u8> {
+    let mut normalized = Vec::with_capacity(code.len());
+    let mut bytes = code.iter().peekable();
+    while let Some(&b) = bytes.next() {
+        if b == b'\r' {
+            bytes.next_if_eq(&&b'\n');
+            normalized.push(b'\n');
+        } else {
+            normalized.push(b);
+        }
+    }
+    let len = normalized
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    normalized.truncate(len);
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ato;
+
+    #[test]
+    fn cross_platform() {
+        let ato = ato::LinkState {
+            language: "python".into(),
+            code: "print(1)\r\n".into(),
+            ..ato::LinkState::ato_ui_default()
+        };
+        let tio = tio::LinkState {
+            language: "python".into(),
+            code: b"print(1)".to_vec(),
+            ..tio::LinkState::new()
+        };
+        let ato = semantic_fingerprint(&ato.into());
+        assert_eq!(ato, semantic_fingerprint(&tio.clone().into()));
+        assert_eq!(ato.language, "python");
+        assert_eq!(ato.code, b"print(1)");
+
+        let other = tio::LinkState {
+            language: "python2".into(),
+            ..tio
+        };
+        assert_ne!(ato, semantic_fingerprint(&other.into()));
+    }
+
+    #[test]
+    fn encodings() {
+        let sbcs = ato::LinkState {
+            language: "jelly".into(),
+            code: "“¡ṭ»".into(),
+            code_encoding: "sbcs".into(),
+            ..ato::LinkState::ato_ui_default()
+        };
+        let tio = tio::LinkState {
+            schema: tio::LinkSchema::V1,
+            domain: tio::LinkDomain::TryItOnline,
+            language: "jelly".into(),
+            code: "“¡ṭ»".into(),
+            ..tio::LinkState::new()
+        };
+        assert_eq!(
+            semantic_fingerprint(&sbcs.into()),
+            semantic_fingerprint(&tio.into()),
+        );
+
+        let base64 = ato::LinkState {
+            language: "retina".into(),
+            code: "cHJpbnQoMSk=".into(),
+            code_encoding: "base64".into(),
+            ..ato::LinkState::ato_ui_default()
+        };
+        let fingerprint = semantic_fingerprint(&base64.into());
+        assert_eq!(fingerprint.code, b"print(1)");
+        let tio = tio::LinkState {
+            language: "retina".into(),
+            code: b"print(1)".to_vec(),
+            ..tio::LinkState::new()
+        };
+        assert_eq!(semantic_fingerprint(&tio.into()).language, "tio:retina");
+    }
+}
@@ -1,2 +1,19 @@
+mod any;
+mod args;
 pub mod ato;
+mod corpus;
+mod examples;
+mod extract;
+mod fingerprint;
+mod options;
+#[cfg(any(test, feature = "testing"))]
+mod testing;
 pub mod tio;
+
+pub use any::*;
+pub use args::*;
+pub use corpus::*;
+pub use examples::*;
+pub use extract::*;
+pub use fingerprint::*;
+pub use options::*;
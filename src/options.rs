@@ -0,0 +1,236 @@
+use std::{fmt, sync::Arc};
+
+use base64::{
+    engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use url::Url;
+
+use crate::extract::host_platform;
+
+/// The base64 alphabets accepted when decoding a link.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Base64Mode {
+    /// Only the URL-safe alphabet (`-` and `_`), which ATO and TIO generate.
+    UrlSafe,
+    /// Only the standard alphabet (`+` and `/`).
+    Standard,
+    /// Either alphabet, even intermixed, like the ATO and TIO frontends
+    /// accept. When decoding with a mode, characters outside both alphabets
+    /// are also removed from ATO links, like ATO does.
+    #[default]
+    Lenient,
+}
+
+/// Options for how tolerant decoding is of malformed links. The default
+/// accepts what the ATO and TIO frontends accept and rejects links they would
+/// decode ambiguously.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Remove characters outside the base64 alphabets, including padding,
+    /// before decoding, like ATO does. TIO does not do this, so it only
+    /// applies to ATO links.
+    pub tolerate_junk_base64: bool,
+    /// Accept the standard base64 alphabet (`+` and `/`), even intermixed
+    /// with the URL-safe alphabet, like both frontends do.
+    pub allow_mixed_alphabet: bool,
+    /// The maximum length of decompressed ATO data, which bounds the memory
+    /// used by DEFLATE bombs. Real links are far smaller, since URLs are short.
+    pub max_decompressed_size: u64,
+    /// Use the data with the maximum schema version when an ATO link has
+    /// several, like ATO does, instead of returning an error.
+    pub prefer_max_schema: bool,
+    /// Collect unknown query keys of an ATO link into `extra_query`, instead
+    /// of returning an error.
+    pub collect_extra_keys: bool,
+    /// When the data of an ATO link does not match the layout of its schema
+    /// version, retry with the layout of the other version, for links from
+    /// frontends that wrote the wrong key for their layout.
+    pub compat_layouts: bool,
+    /// Accept the data of an ATO link with more fields than its schema
+    /// version has, ignoring the extras, or with fewer, filling the missing
+    /// trailing fields with empty strings, so a field added to a schema
+    /// version does not break decoding. It is tried after `compat_layouts`,
+    /// since data with the layout of the other version would otherwise be
+    /// decoded into the wrong fields.
+    pub tolerate_field_count: bool,
+    /// When an ATO link fails to decode, retry with one layer of
+    /// percent-encoding removed from the query string, for links mangled by
+    /// redirectors that encode them twice (e.g., `%253D` for `%3D`).
+    pub undo_double_encoding: bool,
+    /// Remove whitespace from the query string of an ATO link before parsing
+    /// it, for links with line breaks from being wrapped in comments or
+    /// emails. Neither base64 nor the keys contain whitespace, so this is
+    /// lossless. URL parsing already removes tabs and newlines, but not
+    /// spaces or indentation.
+    pub join_wrapped_lines: bool,
+    /// Prepend `https://` to a link without a scheme, when it starts with the
+    /// domain of ATO or TIO, for links pasted as `ato.pxeger.com/run?1=…`.
+    pub add_missing_scheme: bool,
+    /// Called with the number of bytes decompressed so far, as the data of an
+    /// ATO link is decompressed, for showing progress on links with large
+    /// fields. Returning `false` cancels decoding.
+    pub on_decompress: Option<ProgressCallback>,
+}
+
+/// Options for normalizing the text fields of an ATO link when encoding it.
+/// They are off by default, so encoding is lossless; with any on, decoding the
+/// link may not return the original fields.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// Remove a leading byte order mark from each text field, like
+    /// [`LinkState::strip_bom`](crate::ato::LinkState::strip_bom).
+    pub strip_bom: bool,
+    /// Remove zero-width characters from each text field, like
+    /// [`LinkState::strip_zero_width`](crate::ato::LinkState::strip_zero_width).
+    pub strip_zero_width: bool,
+}
+
+/// A callback for the progress of decompressing, which is called with the
+/// number of bytes decompressed so far and returns whether to continue. It can
+/// capture state, like a progress bar or a cancellation token. Callbacks are
+/// equal when they share the same allocation.
+#[derive(Clone)]
+pub struct ProgressCallback(pub Arc<dyn Fn(u64) -> bool + Send + Sync>);
+
+impl DecodeOptions {
+    /// Accept only links exactly as the frontends generate them.
+    pub fn strict() -> Self {
+        DecodeOptions {
+            tolerate_junk_base64: false,
+            allow_mixed_alphabet: false,
+            join_wrapped_lines: false,
+            add_missing_scheme: false,
+            ..DecodeOptions::default()
+        }
+    }
+
+    /// Accept anything that the frontends can open.
+    pub fn lenient() -> Self {
+        DecodeOptions {
+            prefer_max_schema: true,
+            collect_extra_keys: true,
+            compat_layouts: true,
+            tolerate_field_count: true,
+            undo_double_encoding: true,
+            ..DecodeOptions::default()
+        }
+    }
+
+    /// The base64 alphabets to accept.
+    pub(crate) fn base64_mode(&self) -> Base64Mode {
+        if self.allow_mixed_alphabet {
+            Base64Mode::Lenient
+        } else {
+            Base64Mode::UrlSafe
+        }
+    }
+
+    /// Parse a link, adding a missing scheme when `add_missing_scheme`.
+    pub(crate) fn parse_url(&self, url: &str) -> Result<Url, url::ParseError> {
+        match Url::parse(url) {
+            Err(url::ParseError::RelativeUrlWithoutBase) if self.add_missing_scheme => {
+                let host = url.split(['/', '?', '#']).next().unwrap_or_default();
+                if host_platform(host).is_none() {
+                    return Err(url::ParseError::RelativeUrlWithoutBase);
+                }
+                Url::parse(&format!("https://{url}"))
+            }
+            res => res,
+        }
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            tolerate_junk_base64: true,
+            allow_mixed_alphabet: true,
+            max_decompressed_size: 16 << 20,
+            prefer_max_schema: false,
+            collect_extra_keys: false,
+            compat_layouts: false,
+            tolerate_field_count: false,
+            undo_double_encoding: false,
+            join_wrapped_lines: true,
+            add_missing_scheme: true,
+            on_decompress: None,
+        }
+    }
+}
+
+impl ProgressCallback {
+    pub fn new<F: Fn(u64) -> bool + Send + Sync + 'static>(f: F) -> Self {
+        ProgressCallback(Arc::new(f))
+    }
+
+    /// Report the number of bytes decompressed so far and return whether to
+    /// continue.
+    pub fn call(&self, decompressed: u64) -> bool {
+        (self.0)(decompressed)
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback(..)")
+    }
+}
+
+impl PartialEq for ProgressCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ProgressCallback {}
+
+/// Which lenient paths were needed to decode a link, for finding links that
+/// would be worth re-saving in a clean form.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeReport {
+    /// Whether bytes outside the base64 alphabets had to be removed
+    /// before the data decoded (ATO only).
+    pub used_base64_fallback: bool,
+    /// The number of bytes removed by that fallback.
+    pub stripped_bytes: usize,
+    /// Whether a field only decoded after translating the standard base64
+    /// alphabet to the URL-safe alphabet.
+    pub used_standard_alphabet: bool,
+    /// Whether an ATO link only decoded after removing a layer of
+    /// percent-encoding from its query string.
+    pub undid_double_encoding: bool,
+}
+
+impl Base64Mode {
+    /// Decode, recording in `report` whether the standard alphabet was
+    /// translated.
+    pub(crate) fn decode(
+        self,
+        data: &[u8],
+        report: &mut DecodeReport,
+    ) -> Result<Vec<u8>, base64::DecodeError> {
+        match self {
+            Base64Mode::UrlSafe => URL_SAFE_NO_PAD.decode(data),
+            Base64Mode::Standard => STANDARD_NO_PAD.decode(data),
+            Base64Mode::Lenient => match URL_SAFE_NO_PAD.decode(data) {
+                Ok(b) => Ok(b),
+                // Since few links use the standard alphabet, only translate it
+                // when a strict decode fails.
+                Err(err) => {
+                    let data: Vec<u8> = data
+                        .iter()
+                        .map(|&b| match b {
+                            b'+' => b'-',
+                            b'/' => b'_',
+                            _ => b,
+                        })
+                        .collect();
+                    let b = URL_SAFE_NO_PAD.decode(data).map_err(|_| err)?;
+                    report.used_standard_alphabet = true;
+                    Ok(b)
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! [`proptest`] generators for share links, enabled with the `testing`
+//! feature. Generated links roundtrip through their encoder and decoder, so
+//! `decode(encode(x)) == x` holds for any of them:
+//!
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn roundtrip(state: sandbox_links::ato::LinkState) { ... }
+//! }
+//! ```
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use proptest::{collection::vec, prelude::*};
+
+use crate::{ato, tio, Args};
+
+/// Generates ATO states with content that is valid for its encoding: any text
+/// for `utf-8`, text in the code page for `sbcs`, and standard base64 for
+/// `base64`. Options and program arguments are empty or JSON arrays of strings
+/// and are always empty for schema version 0, which has neither. A link with
+/// only a language is encoded without a schema version, so is always version 1.
+impl Arbitrary for ato::LinkState {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let schema = prop_oneof![Just(ato::LinkSchema::V0), Just(ato::LinkSchema::V1)];
+        (
+            schema,
+            "[a-z0-9_]{0,16}",
+            ato_arg_list(),
+            ato_field(),
+            ato_field(),
+            ato_field(),
+            ato_arg_list(),
+            ato_field(),
+        )
+            .prop_map(
+                |(schema, language, options, header, code, footer, program_arguments, input)| {
+                    let mut state = ato::LinkState {
+                        schema,
+                        language,
+                        options,
+                        header: header.0,
+                        header_encoding: header.1,
+                        code: code.0,
+                        code_encoding: code.1,
+                        footer: footer.0,
+                        footer_encoding: footer.1,
+                        program_arguments,
+                        input: input.0,
+                        input_encoding: input.1,
+                        extra_query: Vec::new(),
+                    };
+                    if schema == ato::LinkSchema::V0 {
+                        state.options.clear();
+                        state.program_arguments.clear();
+                    }
+                    if state.is_language_only() {
+                        state.schema = ato::LinkSchema::V1;
+                    }
+                    state
+                },
+            )
+            .boxed()
+    }
+}
+
+/// Generates a field and its encoding.
+fn ato_field() -> impl Strategy<Value = (String, String)> {
+    prop_oneof![
+        any::<String>().prop_map(|text| (text, "utf-8".into())),
+        vec(any::<u8>(), 0..64).prop_map(|b| (ato::decode_sbcs(&b), "sbcs".into())),
+        vec(any::<u8>(), 0..64).prop_map(|b| (STANDARD.encode(b), "base64".into())),
+    ]
+}
+
+/// Generates options or program arguments.
+fn ato_arg_list() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just(String::new()),
+        vec(any::<String>(), 0..4).prop_map(|args| serde_json::to_string(&args).unwrap()),
+    ]
+}
+
+/// Generates TIO states with the v1 schema, since it is the only one that can
+/// be encoded. Languages are restricted to the ids that can be a subdomain of
+/// tryitonline.net.
+impl Arbitrary for tio::LinkState {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let domain = prop_oneof![
+            Just(tio::LinkDomain::Tio),
+            Just(tio::LinkDomain::TioNexus),
+            Just(tio::LinkDomain::TryItOnline),
+        ];
+        (
+            domain,
+            "([a-z0-9][a-z0-9-]{0,15})?",
+            any::<String>(),
+            any::<String>(),
+            vec(any::<String>(), 0..4),
+            any::<bool>(),
+        )
+            .prop_map(
+                |(domain, language, code, input, args, debug)| tio::LinkState {
+                    schema: tio::LinkSchema::V1,
+                    domain,
+                    language,
+                    code: code.into_bytes(),
+                    input: input.into_bytes(),
+                    args: Args(args),
+                    debug,
+                    omit_empty_fields: false,
+                },
+            )
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn roundtrip_ato(state: ato::LinkState) {
+            let url = state.encode().unwrap();
+            prop_assert_eq!(state, ato::LinkState::decode(&url).unwrap());
+        }
+
+        #[test]
+        fn roundtrip_tio(state: tio::LinkState) {
+            let url = state.encode_v1();
+            prop_assert_eq!(state, tio::LinkState::decode_v1(&url).unwrap());
+        }
+    }
+}
@@ -0,0 +1,151 @@
+/// TIO v1 (`<language>.tryitonline.net`) language ids which are known to have
+/// the same id on tio.run, sorted.
+///
+/// Seeded from the languages in `tests/tio_links.txt`. All of those kept their
+/// ids when TIO moved to tio.run, as the updated links on Code Golf show, so
+/// no id is translated. A renamed id would need a table of pairs instead.
+const V1_LANGUAGES: &[&str] = &[
+    "05ab1e",
+    "befunge",
+    "befunge-96-mtfi",
+    "befunge-97-mtfi",
+    "befunge-98",
+    "brachylog",
+    "brainfuck",
+    "cubically",
+    "fish",
+    "fission",
+    "foo",
+    "golfscript",
+    "matl",
+    "retina",
+    "sed",
+    "seriously",
+    "slashes",
+    "smbf",
+    "v",
+];
+
+/// Reports whether a TIO v1 language id is known to have the same id on
+/// tio.run.
+pub fn is_known_v1_language(id: &str) -> bool {
+    V1_LANGUAGES.binary_search(&id).is_ok()
+}
+
+/// tio.run language ids that are aliases for another id, sorted by alias.
+///
+/// This should mirror the aliases in `tests/tio_languages.json`, a snapshot of
+/// tio.run's language list, which is not committed yet. To update it, run
+/// `tests/get_tio_languages.sh` from `tests/` to regenerate the snapshot, then
+/// copy each alias and its target id from it here, keeping the table sorted.
+const ALIASES: &[(&str, &str)] = &[("python", "python3")];
+
+/// Normalize a tio.run language id that is a known alias to its canonical id.
+/// Other ids are returned unchanged. Decoding and [`LinkState::canonicalize`]
+/// do not do this, so call it, or [`LinkState::resolve_language`], explicitly.
+///
+/// [`LinkState::canonicalize`]: crate::tio::LinkState::canonicalize
+/// [`LinkState::resolve_language`]: crate::tio::LinkState::resolve_language
+pub fn resolve_language(id: &str) -> &str {
+    ALIASES
+        .binary_search_by_key(&id, |&(alias, _)| alias)
+        .map_or(id, |i| ALIASES[i].1)
+}
+
+/// tio.run language ids and the ids of the same languages on ATO, sorted by
+/// tio.run id.
+///
+/// Seeded with the languages most common in `tests/ato_links.txt` that are
+/// also on tio.run. Languages whose versions differ enough to run code
+/// differently, like Retina 0.8 and 1, are left out.
+const ATO_LANGUAGES: &[(&str, &str)] = &[
+    ("05ab1e", "05ab1e"),
+    ("awk", "awk"),
+    ("bash", "bash"),
+    ("brainfuck", "brainfuck"),
+    ("c-gcc", "c_gcc"),
+    ("charcoal", "charcoal"),
+    ("cpp-gcc", "cplusplus_gcc"),
+    ("crystal", "crystal"),
+    ("elixir", "elixir"),
+    ("factor", "factor"),
+    ("go", "go"),
+    ("haskell", "haskell"),
+    ("j", "j"),
+    ("java-openjdk", "java"),
+    ("javascript-node", "node"),
+    ("jelly", "jelly"),
+    ("kotlin", "kotlin"),
+    ("lua", "lua"),
+    ("nim", "nim"),
+    ("pari-gp", "pari_gp"),
+    ("perl5", "perl"),
+    ("perl6", "raku"),
+    ("php", "php"),
+    ("pip", "pip"),
+    ("pyth", "pyth"),
+    ("python2", "python2"),
+    ("python3", "python"),
+    ("r", "r"),
+    ("ruby", "ruby"),
+    ("rust", "rust"),
+    ("sed", "sed"),
+    ("slashes", "slashes"),
+    ("tcl", "tcl"),
+    ("zsh", "zsh"),
+];
+
+/// Translate a tio.run language id to the id of the same language on ATO.
+/// Aliases are resolved first. Returns `None` for ids not in the table.
+pub fn ato_language(id: &str) -> Option<&'static str> {
+    let id = resolve_language(id);
+    ATO_LANGUAGES
+        .binary_search_by_key(&id, |&(tio, _)| tio)
+        .ok()
+        .map(|i| ATO_LANGUAGES[i].1)
+}
+
+/// Translate an ATO language id to the id of the same language on tio.run.
+/// Returns `None` for ids not in the table.
+pub fn tio_language(ato_id: &str) -> Option<&'static str> {
+    ATO_LANGUAGES
+        .iter()
+        .find(|&&(_, ato)| ato == ato_id)
+        .map(|&(tio, _)| tio)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted() {
+        assert!(V1_LANGUAGES.windows(2).all(|w| w[0] < w[1]));
+        assert!(ALIASES.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(ATO_LANGUAGES.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn ato() {
+        assert_eq!(ato_language("python"), Some("python"));
+        assert_eq!(ato_language("cpp-gcc"), Some("cplusplus_gcc"));
+        assert_eq!(ato_language("retina"), None);
+        assert_eq!(tio_language("python"), Some("python3"));
+        assert_eq!(tio_language("cplusplus_gcc"), Some("cpp-gcc"));
+        assert_eq!(tio_language("knight"), None);
+    }
+
+    #[test]
+    fn aliases() {
+        assert_eq!(resolve_language("python"), "python3");
+        assert_eq!(resolve_language("python3"), "python3");
+        assert_eq!(resolve_language("sed"), "sed");
+        for &(alias, id) in ALIASES {
+            assert_eq!(
+                resolve_language(id),
+                id,
+                "alias {alias} is not resolved fully"
+            );
+        }
+    }
+}
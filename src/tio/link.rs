@@ -1,21 +1,33 @@
-use std::string::FromUtf8Error;
+use std::{borrow::Cow, fmt, string::FromUtf8Error};
 
-use base64::{
-    engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD},
-    Engine,
-};
+use base64::{display::Base64Display, engine::general_purpose::URL_SAFE_NO_PAD};
+use percent_encoding::percent_decode_str;
 use thiserror::Error;
 use url::Url;
 
+use crate::{
+    corpus::{CorpusFailure, CorpusReport},
+    options::{Base64Mode, DecodeOptions, DecodeReport},
+    tio::{is_known_v1_language, resolve_language},
+    Args,
+};
+
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct LinkState {
     pub schema: LinkSchema,
     pub domain: LinkDomain,
     pub language: String,
-    pub code: String,
-    pub input: String,
-    pub args: Vec<String>,
+    /// The code, as bytes, since not every language uses UTF-8.
+    pub code: Vec<u8>,
+    /// The input, as bytes.
+    pub input: Vec<u8>,
+    pub args: Args,
     pub debug: bool,
+    /// Whether to leave out the `code` and `input` fields when they are
+    /// empty. TIO always wrote them, so this is off by default, but links with
+    /// only a language, like `http://cubically.tryitonline.net/`, have none;
+    /// decoding sets this for them, so they roundtrip exactly.
+    pub omit_empty_fields: bool,
 }
 
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
@@ -25,10 +37,9 @@ pub enum LinkSchema {
     V2,
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum LinkDomain {
     /// TIO v2 (https://tio.run/)
-    #[default]
     Tio,
     /// TIO Nexus (https://tio.run/nexus/)
     TioNexus,
@@ -36,6 +47,20 @@ pub enum LinkDomain {
     TryItOnline,
 }
 
+/// The fields accepted in the fragment of a TIO v1 link. Other fields are
+/// unknown. The language is not a field, but starts the fragment, as in
+/// `#sed#code=…`.
+pub const TIO_FRAGMENT_FIELDS: &[&str] = &["code", "input", "args", "debug"];
+
+/// The domain for new links.
+pub const DEFAULT_DOMAIN: LinkDomain = LinkDomain::Tio;
+
+impl Default for LinkDomain {
+    fn default() -> Self {
+        DEFAULT_DOMAIN
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum DecodeError {
     #[error("URL parse: {0}")]
@@ -54,20 +79,133 @@ pub enum DecodeError {
     Base64(#[from] base64::DecodeError),
     #[error("UTF-8 decode: {0}")]
     Utf8(#[from] FromUtf8Error),
+    #[error("v2 links (`tio.run/##`) are not supported yet")]
+    UnsupportedV2,
 }
 
 #[derive(Debug, Error)]
-pub enum EncodeError {}
+pub enum EncodeError {
+    #[error("write: {0}")]
+    Write(#[from] fmt::Error),
+    #[error("URL parse: {0}")]
+    Url(#[from] url::ParseError),
+}
 
 impl LinkState {
     pub fn new() -> Self {
         LinkState::default()
     }
 
+    /// Set the domain of the link. Since only tio.run supports the v2 format
+    /// and it cannot be encoded yet, this also sets the schema to v1, so the
+    /// link can be encoded with [`LinkState::encode_v1`].
+    pub fn with_domain(mut self, domain: LinkDomain) -> Self {
+        self.domain = domain;
+        self.schema = LinkSchema::V1;
+        self
+    }
+
+    /// Reports whether two links run the same program, even when they are on
+    /// different domains, such as a `tryitonline.net` link and its `tio.run`
+    /// equivalent. The domain, schema, and `omit_empty_fields` are ignored,
+    /// since they only change how the link is written.
+    ///
+    /// `==` compares them too, for exact equality.
+    pub fn content_eq(&self, other: &LinkState) -> bool {
+        LinkState {
+            schema: other.schema,
+            domain: other.domain,
+            omit_empty_fields: other.omit_empty_fields,
+            ..self.clone()
+        } == *other
+    }
+
+    /// Decode a Try It Online share link, detecting its format from the URL:
+    /// links on tio.run with `##` before the state use the v2 format and
+    /// others use the v1 format. Only v1 is supported for now, so v2 links
+    /// return `DecodeError::UnsupportedV2`.
+    pub fn decode(url: &str) -> Result<Self, DecodeError> {
+        // The v1 decoder detects v2 links
+        LinkState::decode_v1(url)
+    }
+
+    /// Decode a Try It Online share link, accepting only links exactly as TIO
+    /// generates them, with [`DecodeOptions::strict`]. Unlike
+    /// [`LinkState::decode`], a link without a scheme is an error.
+    pub fn decode_strict(url: &str) -> Result<Self, DecodeError> {
+        LinkState::decode_with(url, &DecodeOptions::strict())
+    }
+
+    /// Decode a Try It Online share link, with the given tolerance of
+    /// malformed links. Only `allow_mixed_alphabet` and `add_missing_scheme`
+    /// apply to TIO links; the other options are for ATO.
+    pub fn decode_with(url: &str, options: &DecodeOptions) -> Result<Self, DecodeError> {
+        LinkState::decode_v1_parsed(
+            &options.parse_url(url)?,
+            options.base64_mode(),
+            true,
+            &mut DecodeReport::default(),
+        )
+    }
+
     /// Decode a Try It Online share link with the v1 format.
     pub fn decode_v1(url: &str) -> Result<Self, DecodeError> {
-        let u = Url::parse(url)?;
+        LinkState::decode_v1_with_base64_mode(url, Base64Mode::default())
+    }
+
+    /// Decode a Try It Online share link with the v1 format, accepting only the
+    /// base64 alphabets allowed by `mode`.
+    pub fn decode_v1_with_base64_mode(url: &str, mode: Base64Mode) -> Result<Self, DecodeError> {
+        LinkState::decode_v1_parsed(
+            &DecodeOptions::default().parse_url(url)?,
+            mode,
+            true,
+            &mut DecodeReport::default(),
+        )
+    }
+
+    /// Decode a Try It Online share link with the v1 format, keeping the code
+    /// and input as bytes, even when they are not valid UTF-8, which
+    /// [`LinkState::decode_v1`] rejects. Some languages use other encodings.
+    pub fn decode_v1_bytes(url: &str) -> Result<Self, DecodeError> {
+        LinkState::decode_v1_parsed(
+            &DecodeOptions::default().parse_url(url)?,
+            Base64Mode::default(),
+            false,
+            &mut DecodeReport::default(),
+        )
+    }
+
+    /// Decode a Try It Online share link with the v1 format, which has already
+    /// been parsed.
+    pub fn decode_url_parsed(u: &Url) -> Result<Self, DecodeError> {
+        LinkState::decode_v1_parsed(u, Base64Mode::default(), true, &mut DecodeReport::default())
+    }
+
+    /// Decode a Try It Online share link, also reporting which lenient
+    /// decoding paths it needed.
+    pub fn decode_with_report(url: &str) -> Result<(Self, DecodeReport), DecodeError> {
+        let mut report = DecodeReport::default();
+        let state = LinkState::decode_v1_parsed(
+            &DecodeOptions::default().parse_url(url)?,
+            Base64Mode::default(),
+            true,
+            &mut report,
+        )?;
+        Ok((state, report))
+    }
 
+    fn decode_v1_parsed(
+        u: &Url,
+        mode: Base64Mode,
+        utf8: bool,
+        report: &mut DecodeReport,
+    ) -> Result<Self, DecodeError> {
+        // A v2 link would otherwise decode as an empty v1 link, since its
+        // state has no `=`.
+        if is_v2(u) {
+            return Err(DecodeError::UnsupportedV2);
+        }
         let mut language = None;
         let domain = if let Some(domain) = u.domain() {
             if domain == "tio.run" {
@@ -89,7 +227,30 @@ impl LinkState {
             return Err(DecodeError::UnknownDomain("".into()));
         };
 
+        // Some tio.run links have the language in the query, as in
+        // `?lang=sed#code=…`.
+        let query = u.query().unwrap_or_default();
+        for field in query.split('&') {
+            if let Some(l) = field.strip_prefix("lang=") {
+                if language.is_some() {
+                    return Err(DecodeError::MultipleLanguages);
+                }
+                language = Some(percent_decode_str(l).decode_utf8_lossy().into_owned());
+            }
+        }
+
         let mut fragment = u.fragment().unwrap_or_default();
+        // A few archived links put the state in the query instead of the
+        // fragment, so fall back to it when the fragment is empty.
+        let mut in_query = false;
+        if fragment.is_empty()
+            && query
+                .split('&')
+                .any(|field| field.starts_with("code=") || field.starts_with("input="))
+        {
+            fragment = query;
+            in_query = true;
+        }
         if let Some((l, f)) = fragment.split_once('#') {
             if language.is_some() {
                 return Err(DecodeError::MultipleLanguages);
@@ -100,26 +261,37 @@ impl LinkState {
 
         let mut code = None;
         let mut input = None;
+        let mut omit_empty_fields = true;
         let mut args = None;
         let mut debug = None;
         for field in fragment.split('&') {
             if let Some((key, value)) = field.split_once('=') {
+                if key == "code" || key == "input" {
+                    omit_empty_fields = false;
+                }
                 if value.contains('=') {
                     // TIO ignores anything after another `=`, but it is never
                     // encoded like this, so error.
                     return Err(DecodeError::FieldContainsEquals);
                 }
                 match key {
-                    "code" if code.is_none() => code = Some(decode_field(value)?),
-                    "input" if input.is_none() => input = Some(decode_field(value)?),
+                    "code" if code.is_none() => {
+                        code = Some(decode_field_bytes(value, mode, utf8, report)?)
+                    }
+                    "input" if input.is_none() => {
+                        input = Some(decode_field_bytes(value, mode, utf8, report)?);
+                    }
                     "args" if args.is_none() => {
+                        // Empty segments, as in `a++b`, are empty arguments.
                         let a = value
                             .split('+')
-                            .map(decode_field)
+                            .map(|arg| decode_field(arg, mode, report))
                             .collect::<Result<_, DecodeError>>()?;
                         args = Some(a);
                     }
                     "debug" if debug.is_none() => debug = Some(true),
+                    // Already read as the language
+                    "lang" if in_query => {}
                     "code" | "input" | "args" | "debug" => {
                         return Err(DecodeError::DuplicateField(key.into()));
                     }
@@ -134,58 +306,242 @@ impl LinkState {
             language: language.unwrap_or_default(),
             code: code.unwrap_or_default(),
             input: input.unwrap_or_default(),
-            args: args.unwrap_or_default(),
+            args: Args(args.unwrap_or_default()),
             debug: debug.unwrap_or_default(),
+            omit_empty_fields,
         })
     }
 
     /// Encode a Try It Online share link with the v1 format.
+    ///
+    /// The `code` and `input` fields are included, even when empty, like TIO
+    /// generated them, so a link with only input still has `code=`, unless
+    /// `omit_empty_fields` is set.
     pub fn encode_v1(&self) -> String {
-        assert_eq!(self.schema, LinkSchema::V1);
         let mut s = String::new();
+        self.encode_into(&mut s).unwrap();
+        s
+    }
+
+    /// Encode a Try It Online share link with the v1 format as a parsed URL,
+    /// for callers that modify it further. See [`LinkState::encode_v1`].
+    pub fn encode_url(&self) -> Result<Url, EncodeError> {
+        let mut s = String::new();
+        self.encode_into(&mut s)?;
+        Ok(Url::parse(&s)?)
+    }
+
+    /// Encode a Try It Online share link with the v1 format, writing it to
+    /// `w`, without allocating the URL.
+    pub fn encode_into<W: fmt::Write>(&self, w: &mut W) -> Result<(), EncodeError> {
+        assert_eq!(self.schema, LinkSchema::V1);
         match self.domain {
-            LinkDomain::Tio => {
-                s.push_str("https://tio.run/#");
-                s.push_str(&self.language);
+            // An empty language would make it look like a v2 link
+            LinkDomain::Tio if self.language.is_empty() => w.write_str("https://tio.run/")?,
+            LinkDomain::Tio => write!(w, "https://tio.run/#{}", self.language)?,
+            LinkDomain::TioNexus => write!(w, "https://tio.run/nexus/{}", self.language)?,
+            LinkDomain::TryItOnline if self.language.is_empty() => {
+                w.write_str("http://tryitonline.net/")?
+            }
+            LinkDomain::TryItOnline => write!(w, "http://{}.tryitonline.net/", self.language)?,
+        }
+        // The first field starts the fragment and the rest are joined by `&`
+        let mut sep = '#';
+        if !self.omit_empty_fields || !self.code.is_empty() {
+            write!(
+                w,
+                "{sep}code={}",
+                Base64Display::new(&self.code, &URL_SAFE_NO_PAD)
+            )?;
+            sep = '&';
+        }
+        if !self.omit_empty_fields || !self.input.is_empty() {
+            write!(
+                w,
+                "{sep}input={}",
+                Base64Display::new(&self.input, &URL_SAFE_NO_PAD)
+            )?;
+            sep = '&';
+        }
+        for (i, arg) in self.args.iter().enumerate() {
+            if i == 0 {
+                write!(w, "{sep}args=")?;
+                sep = '&';
+            } else {
+                w.write_char('+')?;
             }
+            write!(
+                w,
+                "{}",
+                Base64Display::new(arg.as_bytes(), &URL_SAFE_NO_PAD)
+            )?;
+        }
+        if self.debug {
+            write!(w, "{sep}debug=on")?;
+        }
+        Ok(())
+    }
+
+    /// Strip the link down to its code, for quoting it without the link. The
+    /// v1 format has no header or footer, so this is just the code. Bytes that
+    /// are not valid UTF-8 are replaced.
+    pub fn into_code(self) -> String {
+        String::from_utf8(self.code)
+            .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned())
+    }
+
+    /// Normalize a tio.run language id that is an alias to its canonical id,
+    /// so the run client finds the language. Links on tryitonline.net are
+    /// unchanged, since they use v1 ids. Returns whether the language changed.
+    pub fn resolve_language(&mut self) -> bool {
+        if self.domain == LinkDomain::TryItOnline {
+            return false;
+        }
+        let language = resolve_language(&self.language);
+        if language == self.language {
+            return false;
+        }
+        self.language = language.into();
+        true
+    }
+
+    /// Move the link to tio.run, keeping its language id. TIO v1 ids which
+    /// are not known to exist on tio.run are kept too, but reported as
+    /// [`Canonicalization::Unmapped`], since the link may not open. Links
+    /// without a language stay on tryitonline.net, since tio.run has no
+    /// equivalent form.
+    pub fn canonicalize(&mut self) -> Canonicalization {
+        match self.domain {
+            LinkDomain::Tio => Canonicalization::Unchanged,
             LinkDomain::TioNexus => {
-                s.push_str("https://tio.run/nexus/");
-                s.push_str(&self.language);
+                self.domain = LinkDomain::Tio;
+                Canonicalization::Moved
             }
-            LinkDomain::TryItOnline if self.language == "" => s.push_str("http://tryitonline.net/"),
+            LinkDomain::TryItOnline if self.language.is_empty() => Canonicalization::Unchanged,
             LinkDomain::TryItOnline => {
-                s.push_str("http://");
-                s.push_str(&self.language);
-                s.push_str(".tryitonline.net/")
+                self.domain = LinkDomain::Tio;
+                if is_known_v1_language(&self.language) {
+                    Canonicalization::Moved
+                } else {
+                    Canonicalization::Unmapped
+                }
             }
         }
-        s.push_str("#code=");
-        URL_SAFE_NO_PAD.encode_string(&*self.code, &mut s);
-        s.push_str("&input=");
-        URL_SAFE_NO_PAD.encode_string(&*self.input, &mut s);
-        for (i, arg) in self.args.iter().enumerate() {
-            s.push_str(if i == 0 { "&args=" } else { "+" });
-            URL_SAFE_NO_PAD.encode_string(&*arg, &mut s);
-        }
-        if self.debug {
-            s.push_str("&debug=on");
+    }
+}
+
+/// How [`LinkState::canonicalize`] changed a link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Canonicalization {
+    /// The link is already on tio.run or cannot be moved there.
+    Unchanged,
+    /// The link was moved to tio.run, with a language id known to exist there.
+    Moved,
+    /// The link was moved to tio.run, but its TIO v1 language id is not known
+    /// to exist there.
+    Unmapped,
+}
+
+/// Check that each link in a newline-separated list decodes and re-encodes to
+/// the same URL. Links that differ only by using `+` instead of `-` in base64
+/// or by using https for tryitonline.net count as re-encoding differently.
+pub fn check_roundtrip(links: &str) -> CorpusReport {
+    let mut report = CorpusReport::default();
+    for link in links.lines() {
+        report.total += 1;
+        match check_link(link) {
+            Ok(Roundtrip::Exact) => report.exact += 1,
+            Ok(Roundtrip::EncodingDiffers) => report.encoding_differs += 1,
+            Err(reason) => report.failures.push(CorpusFailure {
+                link: link.into(),
+                reason,
+            }),
         }
-        s
     }
+    report
+}
+
+/// How a link roundtripped.
+enum Roundtrip {
+    Exact,
+    EncodingDiffers,
+}
+
+/// Roundtrip a link and classify how it re-encoded.
+fn check_link(link: &str) -> Result<Roundtrip, String> {
+    let state = LinkState::decode_v1(link).map_err(|err| format!("decoding: {err}"))?;
+    let encoded = state.encode_v1();
+    if encoded == link {
+        return Ok(Roundtrip::Exact);
+    }
+    let mut normalized = url_safe_fields(link);
+    // tryitonline.net links are always encoded with http, like TIO did
+    if state.domain == LinkDomain::TryItOnline {
+        normalized = normalized.replacen("https://", "http://", 1);
+    }
+    if normalized == encoded {
+        return Ok(Roundtrip::EncodingDiffers);
+    }
+    Err(format!("re-encoded as `{encoded}`"))
+}
+
+/// Replace `+` with `-` in the base64 of the `code` and `input` fields, as
+/// they are re-encoded. In `args`, `+` separates the arguments, so it is kept.
+fn url_safe_fields(link: &str) -> String {
+    link.split('&')
+        .map(|segment| {
+            let start = segment.rfind(['#', '?']).map_or(0, |i| i + 1);
+            let (prefix, field) = segment.split_at(start);
+            if field.starts_with("code=") || field.starts_with("input=") {
+                format!("{prefix}{}", field.replace('+', "-"))
+            } else {
+                segment.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Reports whether the URL is a tio.run link with the v2 format, which puts
+/// the state after `##`.
+fn is_v2(u: &Url) -> bool {
+    u.domain() == Some("tio.run")
+        && u.path() == "/"
+        && u.fragment().is_some_and(|f| f.starts_with('#'))
+}
+
+fn decode_field(
+    s: &str,
+    mode: Base64Mode,
+    report: &mut DecodeReport,
+) -> Result<String, DecodeError> {
+    // `escape` with `decodeURIComponent` essentially decodes text as UTF-8.
+    Ok(String::from_utf8(decode_field_bytes(
+        s, mode, false, report,
+    )?)?)
 }
 
-fn decode_field(s: &str) -> Result<String, DecodeError> {
+fn decode_field_bytes(
+    s: &str,
+    mode: Base64Mode,
+    utf8: bool,
+    report: &mut DecodeReport,
+) -> Result<Vec<u8>, DecodeError> {
     // TIO's base64 decoding allows `+` and `/` from the standard alphabet
     // intermixed with `-` and `_` from the URL-safe alphabet, but the encoder
-    // uses URL-safe.
-    let b = match URL_SAFE_NO_PAD.decode(&*s) {
-        Ok(b) => b,
-        // Some links inexplicably use `+`; however, I cannot find when this was
-        // ever the case in the code.
-        Err(err) => STANDARD_NO_PAD.decode(&*s).map_err(|_| err)?,
-    };
-    // `escape` with `decodeURIComponent` essentially decodes text as UTF-8.
-    Ok(String::from_utf8(b)?)
+    // uses URL-safe. Some links inexplicably use `+`; however, I cannot find
+    // when this was ever the case in the code.
+    //
+    // Links copied through some tools have `+` and `/` percent-encoded, so
+    // they are decoded. This is after the fragment is split into fields and
+    // arguments, so an encoded `&` or `+` does not split a value.
+    let s: Cow<[u8]> = percent_decode_str(s).into();
+    let b = mode.decode(&s, report)?;
+    if utf8 {
+        Ok(String::from_utf8(b)?.into_bytes())
+    } else {
+        Ok(b)
+    }
 }
 
 #[cfg(test)]
@@ -203,10 +559,15 @@ mod tests {
             language: "slashes".into(),
             code: "/☃/☃8/☃".into(),
             input: "".into(),
-            args: vec![],
+            args: Args::default(),
             debug: false,
+            omit_empty_fields: false,
         };
         assert_eq!(state, LinkState::decode_v1(url).unwrap());
+        assert_eq!(
+            state,
+            LinkState::decode_url_parsed(&Url::parse(url).unwrap()).unwrap(),
+        );
         assert_eq!(url, state.encode_v1());
 
         // A link with args from 2016-12-20
@@ -218,8 +579,9 @@ mod tests {
             language: "v".into(),
             code: "é\nÀé12|DkJòhé-òó^$/\u{0012}a".into(),
             input: "".into(),
-            args: vec!["--".into(), "-6".into()],
+            args: Args(vec!["--".into(), "-6".into()]),
             debug: false,
+            omit_empty_fields: false,
         };
         assert_eq!(state, LinkState::decode_v1(url).unwrap());
         assert_eq!(url, state.encode_v1());
@@ -233,8 +595,9 @@ mod tests {
             language: "golfscript".into(),
             code: "{(;);}:r;\", \"%(r\\(r n+:c;;.,\\'|'%.,@\\-)):l;0:m;{.,0>}{\" \"m*\\(.,m+:m l\\-\" \"\\*+c@}while".into(),
             input: "\"Hello,|World!|This|is|GolfScript\", \"#\"".into(),
-            args: vec![],
+            args: Args::default(),
             debug: true,
+            omit_empty_fields: false,
         };
         assert_eq!(state, LinkState::decode_v1(url).unwrap());
         assert_eq!(url, state.encode_v1());
@@ -248,8 +611,9 @@ mod tests {
             language: "05ab1e".into(),
             code: "#>!>\\'n\\'r\\'o\\'c\\,,,,,@'s'a'l'a'dJ,é'g'n+'i+'s+'s+'e+'r+'d+.ó[-]+[----->+++<]>.+++++++++++.+++[->+++<]>++.+[--->+<]>.+++H'turkey'~;oooooooo'sweettea.0-5++++++++fffffffff''`'\n#   >99*d1+c3-c89*4+dcc99*2+c!|$l9D3-O93++dOO8+O1+O1-O5+OO1+O95++O.\n# >'p'()'u'()'m'()'p'()'k'()'i'()'n'()'p'()'i'()'e'()\\\nprint'biscuits';'pecanpie'#\"stuffing\"R'c!'o!'r!'n!'b!'r!'e!'a!'d!*\u{001b}ddddddSapplepie".into(),
             input: "".into(),
-            args: vec![],
+            args: Args::default(),
             debug: true,
+            omit_empty_fields: false,
         };
         assert_eq!(state, LinkState::decode_v1(url).unwrap());
         assert_eq!(url, state.encode_v1());
@@ -266,8 +630,9 @@ mod tests {
             language: "05ab1e".into(),
             code: "9LJ.pûvy9yg-úû,".into(),
             input: "".into(),
-            args: vec![],
+            args: Args::default(),
             debug: false,
+            omit_empty_fields: false,
         };
         assert_eq!(state, LinkState::decode_v1(url).unwrap());
         assert_eq!(url, state.encode_v1());
@@ -285,8 +650,9 @@ mod tests {
             language: "retina".into(),
             code: "U=unichr;s=U(39)*3;_=u'''\\n#U=unichr;s=U(39)*3;_=u%s.replace(U(9),U(96));print _%%(s+_+s).replace(U(10),U(92)+'n').replace(U(96),U(9)).replace(U(178),U(179)).replace(U(183),U(184)).replace(U(182),U(183))#|¶#·print\"Wrong language!\"·#?.*t|\"·¶#{2}|^.¶\\n#1\t#\\n\\n#T\t²-¹\t_o\t[^¹]\\nn=chr(10);print n+n.join(['print\"Wrong language!\"','#?.*t|\"'])+n\\n'''.replace(U(9),U(96));print _%(s+_+s).replace(U(10),U(92)+'n').replace(U(96),U(9)).replace(U(178),U(179)).replace(U(183),U(184)).replace(U(182),U(183))#|\n#¶print\"Wrong language!\"¶#?.*t|\"¶\n#{2}|^.\n".into(),
             input: "".into(),
-            args: vec![],
+            args: Args::default(),
             debug: false,
+            omit_empty_fields: false,
         };
         assert_eq!(state, LinkState::decode_v1(url).unwrap());
         assert_eq!(url, state.encode_v1());
@@ -296,6 +662,358 @@ mod tests {
         assert_eq!(url, state.encode_v1());
     }
 
+    #[test]
+    fn encode_url() {
+        let url = "https://tio.run/#sed#code=cC8&input=";
+        let state = LinkState::decode_v1(url).unwrap();
+        let u = state.encode_url().unwrap();
+        assert_eq!(u.as_str(), url);
+        assert_eq!(u.query(), None);
+        assert_eq!(u.fragment(), Some("sed#code=cC8&input="));
+        let u = state
+            .with_domain(LinkDomain::TryItOnline)
+            .encode_url()
+            .unwrap();
+        assert_eq!(u.domain(), Some("sed.tryitonline.net"));
+        assert_eq!(u.fragment(), Some("code=cC8&input="));
+    }
+
+    #[test]
+    fn content_eq() {
+        let states = [
+            "http://05ab1e.tryitonline.net/#code=OUxKLnDDu3Z5OXlnLcO6w7ss&input=",
+            "https://tio.run/nexus/05ab1e#code=OUxKLnDDu3Z5OXlnLcO6w7ss&input=",
+            "https://tio.run/#05ab1e#code=OUxKLnDDu3Z5OXlnLcO6w7ss&input=",
+        ]
+        .map(|url| LinkState::decode_v1(url).unwrap());
+        for a in &states {
+            for b in &states {
+                assert!(a.content_eq(b));
+            }
+        }
+        assert_ne!(states[0], states[1]);
+        assert_ne!(states[1], states[2]);
+        let other = LinkState {
+            input: b"1".to_vec(),
+            ..states[2].clone()
+        };
+        assert!(!states[0].content_eq(&other));
+    }
+
+    #[test]
+    fn with_domain() {
+        let state = LinkState {
+            schema: LinkSchema::V1,
+            code: "1".into(),
+            ..LinkState::default()
+        };
+        assert_eq!(state.domain, DEFAULT_DOMAIN);
+        let state = state.with_domain(LinkDomain::Tio);
+        let url = "https://tio.run/#code=MQ&input=";
+        assert_eq!(url, state.encode_v1());
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+
+        let state = LinkState::new().with_domain(LinkDomain::Tio);
+        assert_eq!(state.schema, LinkSchema::V1);
+        assert_eq!("https://tio.run/#code=&input=", state.encode_v1());
+
+        let mut state = LinkState::new().with_domain(LinkDomain::TioNexus);
+        assert_eq!(state.schema, LinkSchema::V1);
+        state.language = "befunge".into();
+        let url = "https://tio.run/nexus/befunge#code=&input=";
+        assert_eq!(url, state.encode_v1());
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+
+        let state = state.with_domain(LinkDomain::TryItOnline);
+        let url = "http://befunge.tryitonline.net/#code=&input=";
+        assert_eq!(url, state.encode_v1());
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+    }
+
+    #[test]
+    fn bytes() {
+        let mut state = LinkState {
+            schema: LinkSchema::V1,
+            language: "brainfuck".into(),
+            code: b",[.,]".to_vec(),
+            input: vec![0xff, 0x00],
+            ..LinkState::default()
+        };
+        let url = "https://tio.run/#brainfuck#code=LFsuLF0&input=_wA";
+        assert_eq!(url, state.encode_v1());
+        assert_eq!(state.clone().into_code(), ",[.,]");
+        state.code = b"\xff+".to_vec();
+        assert_eq!(state.into_code(), "\u{fffd}+");
+    }
+
+    #[test]
+    fn non_utf8() {
+        let url = "https://tio.run/#brainfuck#code=_ys&input=gA";
+        assert!(matches!(
+            LinkState::decode_v1(url),
+            Err(DecodeError::Utf8(_)),
+        ));
+        let state = LinkState::decode_v1_bytes(url).unwrap();
+        assert_eq!(state.code, b"\xff+");
+        assert_eq!(state.input, b"\x80");
+        assert_eq!(url, state.encode_v1());
+    }
+
+    #[test]
+    fn double_hash() {
+        let url = "https://tio.run/##K8jILM5ITVGwUtBUSVPIKM3NBQA";
+        assert!(matches!(
+            LinkState::decode(url),
+            Err(DecodeError::UnsupportedV2),
+        ));
+        assert!(matches!(
+            LinkState::decode_v1(url),
+            Err(DecodeError::UnsupportedV2),
+        ));
+
+        let url = "https://tio.run/#sed#code=cC8&input=";
+        let state = LinkState {
+            schema: LinkSchema::V1,
+            domain: LinkDomain::Tio,
+            language: "sed".into(),
+            code: "p/".into(),
+            input: "".into(),
+            args: Args::default(),
+            debug: false,
+            omit_empty_fields: false,
+        };
+        assert_eq!(state, LinkState::decode(url).unwrap());
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+    }
+
+    #[test]
+    fn fragment_fields() {
+        for field in TIO_FRAGMENT_FIELDS {
+            LinkState::decode_v1(&format!("https://tio.run/#sed#{field}=")).unwrap();
+        }
+        assert!(matches!(
+            LinkState::decode_v1("https://tio.run/#sed#lang="),
+            Err(DecodeError::UnknownField(field)) if field == "lang",
+        ));
+    }
+
+    #[test]
+    fn percent_encoded() {
+        let state = LinkState {
+            schema: LinkSchema::V1,
+            language: "sed".into(),
+            code: b"p".to_vec(),
+            input: b"a??".to_vec(),
+            args: Args(vec!["a??".into(), "".into()]),
+            ..LinkState::new()
+        };
+        let url = "https://tio.run/#sed#code=cA&input=YT8%2F&args=YT8%2F+";
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+        // An encoded `&` is part of the value, not a separator
+        assert!(matches!(
+            LinkState::decode_v1("https://tio.run/#sed#code=cA%26&input="),
+            Err(DecodeError::Base64(_)),
+        ));
+    }
+
+    #[test]
+    fn missing_scheme() {
+        let state = LinkState::decode("https://tio.run/#sed#code=cC8&input=").unwrap();
+        for url in [
+            "tio.run/#sed#code=cC8&input=",
+            "sed.tryitonline.net/#code=cC8&input=",
+        ] {
+            let decoded = LinkState::decode(url).unwrap();
+            assert_eq!(decoded.language, state.language, "{url}");
+            assert_eq!(decoded.code, state.code, "{url}");
+        }
+        assert!(matches!(
+            LinkState::decode_strict("tio.run/#sed#code=cC8&input="),
+            Err(DecodeError::Url(url::ParseError::RelativeUrlWithoutBase)),
+        ));
+        assert_eq!(
+            state,
+            LinkState::decode_strict("https://tio.run/#sed#code=cC8&input=").unwrap(),
+        );
+    }
+
+    #[test]
+    fn language_in_query() {
+        let state = LinkState {
+            schema: LinkSchema::V1,
+            language: "sed".into(),
+            code: b"p".to_vec(),
+            ..LinkState::new()
+        };
+        for url in [
+            "https://tio.run/?lang=sed#code=cA&input=",
+            "https://tio.run/?code=cA&input=&lang=sed",
+            "https://tio.run/?lang=s%65d#code=cA&input=",
+        ] {
+            assert_eq!(state, LinkState::decode_v1(url).unwrap(), "{url}");
+            assert_eq!(crate::quick_language(url).as_deref(), Some("sed"), "{url}");
+        }
+        for url in [
+            "https://tio.run/?lang=sed#sed#code=cA&input=",
+            "https://tio.run/nexus/sed?lang=sed#code=cA&input=",
+            "https://tio.run/?lang=sed&lang=awk#code=cA&input=",
+        ] {
+            assert!(
+                matches!(
+                    LinkState::decode_v1(url),
+                    Err(DecodeError::MultipleLanguages),
+                ),
+                "{url}",
+            );
+        }
+    }
+
+    #[test]
+    fn language_only() {
+        let url = "http://cubically.tryitonline.net/";
+        let state = LinkState {
+            schema: LinkSchema::V1,
+            domain: LinkDomain::TryItOnline,
+            language: "cubically".into(),
+            omit_empty_fields: true,
+            ..LinkState::new()
+        };
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+        assert_eq!(url, state.encode_v1());
+
+        let state = LinkState {
+            omit_empty_fields: false,
+            ..state
+        };
+        assert_eq!(
+            state.encode_v1(),
+            "http://cubically.tryitonline.net/#code=&input=",
+        );
+        let state = LinkState {
+            omit_empty_fields: true,
+            args: Args(vec!["a".into()]),
+            debug: true,
+            ..state
+        };
+        let url = "http://cubically.tryitonline.net/#args=YQ&debug=on";
+        assert_eq!(url, state.encode_v1());
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+    }
+
+    #[test]
+    fn input_only() {
+        let url = "http://sed.tryitonline.net/#input=MSAy";
+        let state = LinkState {
+            schema: LinkSchema::V1,
+            domain: LinkDomain::TryItOnline,
+            language: "sed".into(),
+            code: "".into(),
+            input: "1 2".into(),
+            args: Args::default(),
+            debug: false,
+            omit_empty_fields: false,
+        };
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+        assert_eq!(
+            "http://sed.tryitonline.net/#code=&input=MSAy",
+            state.encode_v1(),
+        );
+    }
+
+    #[test]
+    fn empty_args() {
+        let url = "https://tio.run/#sed#code=&input=&args=YQ++Yg+";
+        let state = LinkState {
+            schema: LinkSchema::V1,
+            domain: LinkDomain::Tio,
+            language: "sed".into(),
+            code: "".into(),
+            input: "".into(),
+            args: Args(vec!["a".into(), "".into(), "b".into(), "".into()]),
+            debug: false,
+            omit_empty_fields: false,
+        };
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+        assert_eq!(url, state.encode_v1());
+        let mut s = String::from("<");
+        state.encode_into(&mut s).unwrap();
+        assert_eq!(s, format!("<{url}"));
+
+        let url = "https://tio.run/#sed#code=&input=&args=";
+        let state = LinkState {
+            args: Args(vec!["".into()]),
+            ..state
+        };
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+        assert_eq!(url, state.encode_v1());
+    }
+
+    #[test]
+    fn resolve_language() {
+        let url = "https://tio.run/#python#code=cHJpbnQoMSk&input=";
+        let mut state = LinkState::decode_v1(url).unwrap();
+        assert!(state.resolve_language());
+        assert_eq!(state.language, "python3");
+        assert!(!state.resolve_language());
+
+        let mut state = state.with_domain(LinkDomain::TryItOnline);
+        state.language = "python".into();
+        assert!(!state.resolve_language());
+        assert_eq!(state.language, "python");
+    }
+
+    #[test]
+    fn canonicalize() {
+        let mut state = LinkState::decode_v1(
+            "http://05ab1e.tryitonline.net/#code=OUxKLnDDu3Z5OXlnLcO6w7ss&input=",
+        )
+        .unwrap();
+        assert_eq!(state.canonicalize(), Canonicalization::Moved);
+        assert_eq!(
+            state.encode_v1(),
+            "https://tio.run/#05ab1e#code=OUxKLnDDu3Z5OXlnLcO6w7ss&input=",
+        );
+        assert_eq!(state.canonicalize(), Canonicalization::Unchanged);
+
+        let mut state = LinkState::decode_v1("http://tryitonline.net/#code=&input=").unwrap();
+        assert_eq!(state.canonicalize(), Canonicalization::Unchanged);
+        assert_eq!(state.domain, LinkDomain::TryItOnline);
+
+        let mut state =
+            LinkState::decode_v1("http://unknown.tryitonline.net/#code=&input=").unwrap();
+        assert_eq!(state.canonicalize(), Canonicalization::Unmapped);
+        assert_eq!(state.encode_v1(), "https://tio.run/#unknown#code=&input=");
+
+        for link in include_str!("../../tests/tio_links.txt").lines() {
+            let mut state = LinkState::decode_v1(link).unwrap();
+            assert_ne!(
+                state.canonicalize(),
+                Canonicalization::Unmapped,
+                "unknown language in `{link}`",
+            );
+        }
+    }
+
+    #[test]
+    fn state_in_query() {
+        let url = "http://slashes.tryitonline.net/?code=L-KYgy_imIM4L-KYgw&input=";
+        let state = LinkState {
+            schema: LinkSchema::V1,
+            domain: LinkDomain::TryItOnline,
+            language: "slashes".into(),
+            code: "/☃/☃8/☃".into(),
+            input: "".into(),
+            args: Args::default(),
+            debug: false,
+            omit_empty_fields: false,
+        };
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+        assert_eq!(
+            "http://slashes.tryitonline.net/#code=L-KYgy_imIM4L-KYgw&input=",
+            state.encode_v1(),
+        );
+    }
+
     #[test]
     fn base64_plus() {
         // These three links from the same post inexplicably use `+` in base64
@@ -309,10 +1027,16 @@ mod tests {
             language: "befunge-98".into(),
             code: "r@;\"@_,#:>\"'\"\"A1j@\"'\"::''\\\"@_,#:>\"'\"\"\\''::\"'\":''\\\"PPCG\"'\"\"\\''::\"'\":''\\\"0@#j1\"'\"::''\\\">:#,_@\"'\"\";@;r\"'>k,@>;#@k!k1".into(),
             input: "".into(),
-            args: vec![],
+            args: Args::default(),
             debug: false,
+            omit_empty_fields: false,
         };
         assert_eq!(state, LinkState::decode_v1(url).unwrap());
+        let (_, report) = LinkState::decode_with_report(url).unwrap();
+        assert!(report.used_standard_alphabet);
+        assert!(LinkState::decode_with(url, &DecodeOptions::strict()).is_err());
+        let (_, report) = LinkState::decode_with_report(&state.encode_v1()).unwrap();
+        assert!(!report.used_standard_alphabet);
         let url = "http://befunge.tryitonline.net/#code=MWojQDAiR0NQUCI+OiMsX0A&input=";
         let state = LinkState {
             schema: LinkSchema::V1,
@@ -320,8 +1044,9 @@ mod tests {
             language: "befunge".into(),
             code: "1j#@0\"GCPP\">:#,_@".into(),
             input: "".into(),
-            args: vec![],
+            args: Args::default(),
             debug: false,
+            omit_empty_fields: false,
         };
         assert_eq!(state, LinkState::decode_v1(url).unwrap());
 
@@ -333,40 +1058,60 @@ mod tests {
             language: "befunge-96-mtfi".into(),
             code: "A1j@\"@_,#:>\"'\"\"PPCG\"'\"\"0@#j1\">:#,_@".into(),
             input: "".into(),
-            args: vec![],
+            args: Args::default(),
             debug: false,
+            omit_empty_fields: false,
         };
         assert_eq!(state, LinkState::decode_v1(url).unwrap());
+        assert_eq!(
+            state,
+            LinkState::decode_v1_with_base64_mode(url, Base64Mode::Standard).unwrap(),
+        );
+        assert!(matches!(
+            LinkState::decode_v1_with_base64_mode(url, Base64Mode::UrlSafe),
+            Err(DecodeError::Base64(_)),
+        ));
+    }
+
+    #[test]
+    fn base64_mixed() {
+        let url = "http://fish.tryitonline.net/#code=fn4/Pz4-&input=";
+        let state = LinkState {
+            schema: LinkSchema::V1,
+            domain: LinkDomain::TryItOnline,
+            language: "fish".into(),
+            code: "~~??>>".into(),
+            input: "".into(),
+            args: Args::default(),
+            debug: false,
+            omit_empty_fields: false,
+        };
+        assert_eq!(state, LinkState::decode_v1(url).unwrap());
+        for mode in [Base64Mode::UrlSafe, Base64Mode::Standard] {
+            assert!(matches!(
+                LinkState::decode_v1_with_base64_mode(url, mode),
+                Err(DecodeError::Base64(_)),
+            ));
+        }
+    }
+
+    #[test]
+    fn check_roundtrip_alphabet() {
+        let report = check_roundtrip(
+            "https://tio.run/#sed#code=YT4+&input=\n\
+             https://v.tryitonline.net/#code=YT4+&input=&args=LS0+LTY+YT4",
+        );
+        assert!(report.is_ok(), "{report}");
+        assert_eq!(report.encoding_differs, 2);
     }
 
     #[test]
     fn roundtrip_all() {
         let links = include_str!("../../tests/tio_links.txt");
-        let mut fail = false;
-        for link in links.lines() {
-            let state = match LinkState::decode_v1(link) {
-                Ok(state) => state,
-                Err(err) => panic!("decoding `{link}`: {err}"),
-            };
-            let encoded = state.encode_v1();
-            if encoded != link {
-                // Language-only links like http://cubically.tryitonline.net/
-                // encode with empty fragment fields
-                if state.domain == LinkDomain::TryItOnline
-                    && Url::parse(link).unwrap().fragment().is_none()
-                {
-                    continue;
-                }
-                if link.replace("+", "-") == encoded {
-                    println!("Differ by `+`: `{link}` encodes to `{encoded}`");
-                    continue;
-                }
-                println!("encoding `{link}`: got `{encoded}` from {state:?}");
-                fail = true;
-            }
-        }
-        if fail {
-            panic!("failed");
-        }
+        let report = check_roundtrip(links);
+        assert!(report.is_ok(), "{report}");
+        // Links with the standard base64 alphabet or with https for
+        // tryitonline.net
+        assert_eq!(report.encoding_differs, 4, "{report}");
     }
 }
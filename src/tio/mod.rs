@@ -1,5 +1,7 @@
 //! A decoder and encoder for Try It Online code share links.
 
+mod languages;
 mod link;
 
+pub use languages::*;
 pub use link::*;
@@ -0,0 +1,5 @@
+#!/bin/bash
+set -euo pipefail
+
+curl -fsS 'https://tio.run/languages.json' |
+  jq -S . > tio_languages.json